@@ -1,19 +1,53 @@
 use crate::vec;
 use crate::utils;
+use crate::integrator;
+use crate::gravity;
+use crate::field;
+use crate::params;
 
 #[derive(Clone)]
 pub struct Update <'a> {
     pub w: f32,
     pub h: f32,
-    pub gravity_f: f32,
+    pub dt: f32,
+    pub time: f32,
     pub agents: &'a crate::latex::Latex2D<Agent>,
-    pub gravity: Vec<vec::Vec>,
+    pub gravity: Vec<gravity::Well>,
+    pub fields: &'a field::Fields,
+    pub integrator: integrator::Integrator,
+    pub params: &'a params::Params,
+}
+
+impl<'a> Update<'a> {
+    pub fn external_accel(&self, pos: &vec::Vec) -> vec::Vec {
+        let mut acc = vec::Vec::new();
+        for g in self.gravity.iter() {
+            acc.add(&g.accel(pos));
+        }
+        acc
+    }
+
+    // Potential of `external_accel` per unit of mass
+    pub fn potential(&self, pos: &vec::Vec) -> f32 {
+        self.gravity.iter().map(|g| g.potential(pos)).sum::<f32>() + self.fields.potential(pos)
+    }
+
+    // Kinetic plus potential energy of the whole swarm
+    pub fn energy(&self, agents: &[Agent]) -> f64 {
+        agents.iter().map(|x| {
+            (0.5 * x.vel.mag().powi(2) + self.potential(&x.pos)) as f64
+        }).sum()
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct Agent {
     pub id: usize,
+    // Picks the behaviour, one in 12 leads, the others follow the lowest
+    // class around; set at creation as ids change when agents are erased
+    pub class: usize,
     pub pos: vec::Vec,
+    pub prev_pos: vec::Vec,
     pub vel: vec::Vec,
     pub radius: f32,
     pub view_range: f32,
@@ -24,6 +58,8 @@ pub struct Agent {
     pub weirdness: f32,
     pub s_in_range: usize,
     pub s_vel: f32,
+    pub s_acc: f32,
+    pub frozen: bool,
     pub color: [f32; 3],
 }
 
@@ -31,9 +67,13 @@ impl Agent {
     pub fn new(id: usize, pos: vec::Vec, vel: vec::Vec) -> Agent {
         let mut a = Agent {
             id,
+            class: id % 12,
             pos,
+            prev_pos: pos,
             vel,
             s_vel: 0.0,
+            s_acc: 0.0,
+            frozen: false,
             s_in_range: 0,
             color: [
                 // 0.1,0.1,0.1,
@@ -77,43 +117,55 @@ impl Agent {
         (self.color[0] + self.color[1] + self.color[2]) / 3.0
     }
 
+    // Position between the previous and the current step, `alpha` in [0, 1]
+    pub fn lerp_pos(&self, alpha: f32, w: f32, h: f32) -> vec::Vec {
+        let mut p = self.prev_pos.rel(&self.pos, w, h);
+        p.sub(&self.prev_pos).mul(alpha).add(&self.prev_pos);
+        if p.x > w { p.x-= w }
+        if p.x < 0.0 { p.x+= w }
+        if p.y > h { p.y-= h }
+        if p.y < 0.0 { p.y+= h }
+        p
+    }
+
     pub fn update(&mut self, update: &Update) {
         // let mut tim = utils::Timer::new("AGENT");
+        let (pw, ph) = update.fields.period(update.w, update.h);
         let mut in_range_incl = update.agents.get((self.pos.x, self.pos.y), self.view_range);
         // tim.tick("latex GET");
         in_range_incl.retain(|x| {
-            let d = self.pos.dist_mod(&x.pos, update.w, update.h);
+            let d = self.pos.dist_mod(&x.pos, pw, ph);
             d < self.view_range
         });
-        if self.id % 12 > 0 {
+        if self.class > 0 {
             let mut closest: Option<(usize, f32)> = None;
             in_range_incl.iter().enumerate().for_each(|(i, x)| {
                 if x.id == self.id { return; }
-                let d = self.pos.dist_mod(&x.pos, update.w, update.h);
+                let d = self.pos.dist_mod(&x.pos, pw, ph);
                 if closest.is_none() || closest.unwrap().1 < d {
                     closest = Some((i, d));
                 }
             });
             if closest.is_some() {
                 let closest = in_range_incl[closest.unwrap().0];
-                utils::eavg(&mut self.color[0], closest.color[0], 0.01);
-                utils::eavg(&mut self.color[1], closest.color[1], 0.01);
-                utils::eavg(&mut self.color[2], closest.color[2], 0.01);
+                utils::eavg(&mut self.color[0], closest.color[0], 0.01 * update.dt);
+                utils::eavg(&mut self.color[1], closest.color[1], 0.01 * update.dt);
+                utils::eavg(&mut self.color[2], closest.color[2], 0.01 * update.dt);
             }
 
             let mut closest: Option<(usize, usize)> = None;
             in_range_incl.iter().enumerate().for_each(|(i, x)| {
                 if x.id == self.id { return; }
-                if closest.is_none() || closest.unwrap().1 > x.id%12 {
-                    closest = Some((i, x.id%12));
+                if closest.is_none() || closest.unwrap().1 > x.class {
+                    closest = Some((i, x.class));
                 }
             });
 
             if closest.is_some() {
                 let closest = in_range_incl[closest.unwrap().0];
-                utils::eavg(&mut self.color[0], closest.color[0], 0.01);
-                utils::eavg(&mut self.color[1], closest.color[1], 0.01);
-                utils::eavg(&mut self.color[2], closest.color[2], 0.01);
+                utils::eavg(&mut self.color[0], closest.color[0], 0.01 * update.dt);
+                utils::eavg(&mut self.color[1], closest.color[1], 0.01 * update.dt);
+                utils::eavg(&mut self.color[2], closest.color[2], 0.01 * update.dt);
             }
         }
         // assert!(self.view_range > 0.0);
@@ -123,19 +175,51 @@ impl Agent {
         //     self.id != x.id
         // });
 
-            self.vel_w = 0.1 + self.color[0] * 2.0;
-            self.pos_w = 0.1 + self.color[1] * 0.8;
+            let p = update.params;
+            self.vel_w = p.vel_w_base + self.color[0] * p.vel_w_red;
+            self.pos_w = p.pos_w_base + self.color[1] * p.pos_w_green;
 
             // self.pos_w = (self.pos_w / 100.0).max(0.01);
             // self.vel_w = (self.vel_w / 100.0).max(0.01);
-            self.view_range = (10.0 + 40.0 * self.color[2]) * 0.5;
+            self.view_range = (p.view_base + p.view_blue * self.color[2]) * p.view_scale;
             self.pos_w*= -1.0;
-            self.drag = self.color[0] * 0.001 + self.color[2] * 0.01;
+            self.drag = self.color[0] * p.drag_red + self.color[2] * p.drag_blue;
+            self.weirdness = p.weirdness;
 
         // tim.tick("retain in range");
 
 
-        if !in_range_incl.is_empty() {
+        if self.frozen {
+            self.vel = vec::Vec::new();
+            self.s_acc = 0.0;
+        } else {
+            let state = integrator::State { pos: self.pos, vel: self.vel };
+            let next = update.integrator.step(state, update.dt, |s| self.accel(s, &in_range_incl, update));
+            self.s_acc = next.vel.dist(&self.vel) / update.dt;
+            self.pos = next.pos;
+            self.vel = next.vel;
+        }
+
+        // if self.pos.y > update.h {
+        //     self.vel.y*= -0.8;
+        //     self.pos.y = update.h - (update.h - self.pos.y).abs();
+        // }
+
+        update.fields.apply_boundary(&mut self.pos, &mut self.vel, update.w, update.h);
+
+        self.s_vel = self.vel.mag();
+        self.s_in_range = in_range_incl.len();
+        // tim.tick("finish");
+        // tim.show();
+    }
+
+    // Acceleration felt in state `s`, neighbours are frozen at the start of
+    // the step so integrators can evaluate it at trial states.
+    pub fn accel(&self, s: &integrator::State, in_range: &[&Agent], update: &Update) -> vec::Vec {
+        let mut acc = vec::Vec::new();
+        let (pw, ph) = update.fields.period(update.w, update.h);
+
+        if in_range.iter().any(|x| x.id != self.id) {
             // let mut entour = [0.0,0.0,0.0];
             // let mut dom: Option<(f32, &Agent)> = None;
             // in_range_incl.iter().for_each(|x| {
@@ -179,25 +263,26 @@ impl Agent {
 
 
             let mut avg_vel = vec::Vec::new();
-            in_range_incl.iter().for_each(|x| {
-                let mut d = self.pos.dist_mod(&x.pos, update.w, update.h);
+            in_range.iter().filter(|x| x.id != self.id).for_each(|x| {
+                let mut d = s.pos.dist_mod(&x.pos, pw, ph);
                 d/= self.view_range;
                 // d+= 1.0;
                 // d*= 1.0;
-                avg_vel.sub(&self.vel.clone().sub(&x.vel).mul(1.0-d));
+                let mut rel_vel = s.vel;
+                avg_vel.sub(rel_vel.sub(&x.vel).mul(1.0-d));
                 // avg_vel.sub(self.vel.clone().sub(&x.vel).mul(1.0/(d.powi(2))));
             });
-            avg_vel.div(in_range_incl.len() as f32);
+            avg_vel.div(in_range.len() as f32);
             // tim.tick("avg_vel");
 
             let mut avg_pos = vec::Vec::new();
-            in_range_incl.iter().for_each(|x| {
-                let mut diff = self.pos.clone();
-                diff.sub(&self.pos.rel(&x.pos, update.w, update.h));
+            in_range.iter().filter(|x| x.id != self.id).for_each(|x| {
+                let mut diff = s.pos;
+                diff.sub(&s.pos.rel(&x.pos, pw, ph));
                 diff.div(diff.mag().max(1.0).powi(2) * 0.01);
                 avg_pos.sub(&diff);
             });
-            avg_pos.div(in_range_incl.len() as f32);
+            avg_pos.div(in_range.len() as f32);
             // tim.tick("avg_pos");
 
             avg_vel.norm(1.0);
@@ -210,46 +295,21 @@ impl Agent {
             // let mut diff = avg_pos;
             // diff.limit(self.max_acc);
             // diff.norm(0.5);
-            diff.mul(0.1);
-            self.vel.add(&diff);
-        }
-
-        // self.vel.limit(10.0);
-        self.pos.add(&self.vel);
-        self.vel.mul(1.0-self.drag);
-
-
-        for mut g in update.gravity.iter().cloned() {
-            // let mut g = vec::Vec::new_from(update.w, update.h);
-            g.sub(&self.pos);
-            let mag = g.mag();
-            g.mul(update.gravity_f.powf(1.4)*0.2/(100.0 + mag*mag));
-            self.vel.add(&g);
+            diff.mul(update.params.acc_scale);
+            acc.add(diff);
         }
-        // if self.pos.y > update.h {
-        //     self.vel.y*= -0.8;
-        //     self.pos.y = update.h - (update.h - self.pos.y).abs();
-        // }
 
-        while self.pos.x > update.w { self.pos.x-= update.w }
-        while self.pos.x < 0.0 { self.pos.x+= update.w }
-        while self.pos.y > update.h { self.pos.y-= update.h }
-        while self.pos.y < 0.0 { self.pos.y+= update.h }
+        // Continuous version of `vel *= 1 - drag` per unit of time
+        let mut drag = s.vel;
+        acc.add(drag.mul((1.0 - self.drag).ln()));
 
-        self.s_vel = self.vel.mag();
-        self.s_in_range = in_range_incl.len();
-        // tim.tick("finish");
-        // tim.show();
+        acc.add(&update.external_accel(&s.pos));
+        acc.add(&update.fields.accel(&s.pos, &s.vel, update.time));
+        acc
     }
 
-    pub fn draw(&self, _ctx: &mut ggez::Context,
-                mb: &mut ggez::graphics::MeshBuilder,
-                _mb_bg: &mut ggez::graphics::MeshBuilder,
-                max_vel: f32,
-                max_range: f32) {
-        use ggez::graphics;
-
-
+    // Own colour, more opaque the faster the agent goes compared to `max_vel`
+    pub fn draw_color(&self, max_vel: f32) -> [f32; 4] {
         let mut g = self.s_vel as f32 / max_vel * 1.5;
         let mut q = 1.0f32;// self.s_in_range as f32 / max_range;
 
@@ -262,15 +322,32 @@ impl Agent {
         //     q,
         //     g,
         //     q*g, (q*g).max(0.1));
-        let col = graphics::Color::new(
-            self.color[0],
-            self.color[1],
-            self.color[2],
-            (q + g) / 2.0);
+        [self.color[0], self.color[1], self.color[2], (q + g) / 2.0]
+    }
+
+    pub fn draw(&self, _ctx: &mut ggez::Context,
+                mb: &mut ggez::graphics::MeshBuilder,
+                _mb_bg: &mut ggez::graphics::MeshBuilder,
+                max_vel: f32,
+                max_range: f32,
+                alpha: f32) {
+        self.draw_rgba(_ctx, mb, self.draw_color(max_vel), alpha);
+    }
+
+    // Same circle as `draw`, in the given colour
+    pub fn draw_rgba(&self, _ctx: &mut ggez::Context,
+                     mb: &mut ggez::graphics::MeshBuilder,
+                     [r, g, b, a]: [f32; 4],
+                     alpha: f32) {
+        use ggez::graphics;
+
+        let col = graphics::Color::new(r, g, b, a);
         // let col = graphics::Color::new(1.0,1.0,1.0, (q*g).max(0.4));
+        let (w, h) = graphics::drawable_size(_ctx);
+        let pos = self.lerp_pos(alpha, w, h);
         mb.circle(
             graphics::DrawMode::fill(),
-            ggez::nalgebra::Point2::new(self.pos.x, self.pos.y),
+            ggez::nalgebra::Point2::new(pos.x, pos.y),
             2.8,
             1.0,
             // graphics::Color::new(q/2.0+0.1, g, q*g, (g*q).max(0.1)),
@@ -0,0 +1,177 @@
+use crate::ag::Agent;
+use crate::latex::Latex2D;
+use crate::utils;
+use crate::vec;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tool {
+    Push,
+    Swirl,
+    Attract,
+    Repel,
+    Spawn,
+    Erase,
+    Paint,
+    Freeze,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Falloff {
+    Constant,
+    Linear,
+    Smooth,
+    Gaussian,
+}
+
+impl Falloff {
+    pub fn next(self) -> Falloff {
+        match self {
+            Falloff::Constant => Falloff::Linear,
+            Falloff::Linear => Falloff::Smooth,
+            Falloff::Smooth => Falloff::Gaussian,
+            Falloff::Gaussian => Falloff::Constant,
+        }
+    }
+}
+
+pub struct Brush {
+    pub tool: Tool,
+    pub radius: f32,
+    pub strength: f32,
+    pub falloff: Falloff,
+    pub color: [f32; 3],
+    // Spawn stops adding agents past this many
+    pub max_agents: usize,
+}
+
+impl Brush {
+    pub fn new(radius: f32, max_agents: usize) -> Brush {
+        Brush {
+            tool: Tool::Push,
+            radius,
+            strength: 1.0,
+            falloff: Falloff::Linear,
+            color: [1.0, 0.0, 0.0],
+            max_agents,
+        }
+    }
+
+    pub fn select(&mut self, n: usize) {
+        self.tool = match n {
+            1 => Tool::Push,
+            2 => Tool::Swirl,
+            3 => Tool::Attract,
+            4 => Tool::Repel,
+            5 => Tool::Spawn,
+            6 => Tool::Erase,
+            7 => Tool::Paint,
+            8 => Tool::Freeze,
+            _ => self.tool,
+        };
+    }
+
+    // Weight of the brush at `dist` from its centre, 0 outside the radius
+    pub fn weight(&self, dist: f32) -> f32 {
+        if dist > self.radius { return 0.0; }
+        let x = dist / self.radius;
+        match self.falloff {
+            Falloff::Constant => 1.0,
+            Falloff::Linear => 1.0 - x,
+            Falloff::Smooth => 1.0 - x * x * (3.0 - 2.0 * x),
+            Falloff::Gaussian => (-4.0 * x * x).exp(),
+        }
+    }
+
+    // Ids and weights of the agents under the brush
+    fn under(&self, latex: &Latex2D<Agent>, p: &vec::Vec, period: (f32, f32)) -> Vec<(usize, f32)> {
+        latex.get((p.x, p.y), self.radius).iter()
+            .map(|x| (x.id, self.weight(x.pos.dist_mod(p, period.0, period.1))))
+            .filter(|x| x.1 > 0.0)
+            .collect()
+    }
+
+    // Push follows the mouse, so it is driven by motion events
+    pub fn push(&self, agents: &mut [Agent], latex: &Latex2D<Agent>, p: vec::Vec, d: vec::Vec, period: (f32, f32)) {
+        self.under(latex, &p, period).into_iter().for_each(|(id, w)| {
+            let x = agents.get_mut(id).expect("element in latex too much");
+            let mut d = d;
+            // d.mul(2.0);
+            d.mul(w * 0.1 * self.strength);
+            x.vel.add(&d);
+        });
+    }
+
+    // Applied once per step while a button is held, `invert` is the right
+    // button and swaps each tool with its opposite. Returns true if agents
+    // were added or removed.
+    pub fn apply(&self, agents: &mut Vec<Agent>, latex: &Latex2D<Agent>, p: vec::Vec, invert: bool, dt: f32, period: (f32, f32)) -> bool {
+        let sign = if invert { -1.0 } else { 1.0 };
+        let k = self.strength * dt;
+        match self.tool {
+            Tool::Push => false,
+            Tool::Swirl | Tool::Attract | Tool::Repel => {
+                self.under(latex, &p, period).into_iter().for_each(|(id, w)| {
+                    let x = agents.get_mut(id).expect("element in latex too much");
+                    let mut r = x.pos.rel(&p, period.0, period.1);
+                    r.sub(&x.pos).norm(1.0);
+                    let acc = match self.tool {
+                        Tool::Swirl => vec::Vec::new_from(-r.y, r.x),
+                        Tool::Repel => vec::Vec::new_from(-r.x, -r.y),
+                        _ => r,
+                    };
+                    x.vel.add(&vec::Vec::new_from(acc.x * w * k * sign * 0.2, acc.y * w * k * sign * 0.2));
+                });
+                false
+            }
+            Tool::Spawn | Tool::Erase if (self.tool == Tool::Spawn) != invert => {
+                let n = ((k * 2.0).ceil() as usize).min(self.max_agents.saturating_sub(agents.len()));
+                if n == 0 { return false; }
+                for _ in 0..n {
+                    let a = utils::rand_float(0.0, std::f32::consts::PI * 2.0);
+                    let r = self.radius * utils::rand_float(0.0, 1.0).sqrt();
+                    let pos = vec::Vec::new_from(p.x + a.cos() * r, p.y + a.sin() * r);
+                    if pos.x < 0.0 || pos.y < 0.0 || pos.x >= latex.w || pos.y >= latex.h { continue; }
+                    let mut x = Agent::new(agents.len(), pos, vec::Vec::new());
+                    x.color = self.color;
+                    agents.push(x);
+                }
+                true
+            }
+            Tool::Spawn | Tool::Erase => {
+                let erase: std::collections::HashSet<usize> = self.under(latex, &p, period).into_iter()
+                    .filter(|(_, w)| utils::maybe(w * k * 0.2))
+                    .map(|(id, _)| id)
+                    .collect();
+                if erase.is_empty() { return false; }
+                agents.retain(|x| !erase.contains(&x.id));
+                agents.iter_mut().enumerate().for_each(|(i, x)| x.id = i);
+                true
+            }
+            // Right click picks the colour up instead, see `pick_color`
+            Tool::Paint if invert => false,
+            Tool::Paint => {
+                self.under(latex, &p, period).into_iter().for_each(|(id, w)| {
+                    let x = agents.get_mut(id).expect("element in latex too much");
+                    x.color.iter_mut().zip(self.color.iter()).for_each(|(c, b)| utils::eavg(c, *b, w * k * 0.1));
+                });
+                false
+            }
+            Tool::Freeze => {
+                self.under(latex, &p, period).into_iter().for_each(|(id, _)| {
+                    agents.get_mut(id).expect("element in latex too much").frozen = !invert;
+                });
+                false
+            }
+        }
+    }
+
+    // Average colour under the brush, used as eyedropper for the paint tool
+    pub fn pick_color(&mut self, latex: &Latex2D<Agent>, p: vec::Vec, period: (f32, f32)) {
+        let under = latex.get((p.x, p.y), self.radius);
+        let under: Vec<&&Agent> = under.iter().filter(|x| x.pos.dist_mod(&p, period.0, period.1) < self.radius).collect();
+        if under.is_empty() { return; }
+        let mut col = [0.0, 0.0, 0.0];
+        under.iter().for_each(|x| col = utils::sum(&col, &x.color));
+        self.color = col.map(|c| c / under.len() as f32);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::metrics;
+use crate::term;
+
+pub const RECORD_FILE: &str = "session.jsonl";
+// Headless runs don't time the latex cell size, the neighbour order it gives
+// changes the results
+pub const LATEX_DIV: f32 = 16.0;
+
+pub const USAGE: &str = "usage: game [--seed N] [--record FILE | --no-record] [--replay FILE]
+                 [--metrics FILE|- [--metrics-every N]]
+       game --headless [--seed N] [--steps N] [--every N] [--size WxH] [--latex-div N]
+                      [--metrics FILE|- [--metrics-every N]]
+                      [--resolution WxH] [--png DIR] [--y4m FILE|-]
+                      [--svg DIR [--svg-arrows]]
+                      [--term braille|blocks [--term-size COLSxROWS] [--fps N]]
+                      [--vtk DIR] [--csv DIR] [--npy DIR] [--contours DIR]";
+
+pub struct Args {
+    pub seed: Option<u64>,
+    // Where the input of this session is written, None to not record
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    // Run without a window, rendering to the outputs below
+    pub headless: bool,
+    pub steps: u64,
+    // Steps between two rendered frames
+    pub every: u64,
+    pub size: (f32, f32),
+    // Latex cells per half world width, fixed so a seed gives the same run
+    pub latex_div: f32,
+    // Size of the rendered frames, the world size if None
+    pub resolution: Option<(f32, f32)>,
+    pub png: Option<String>,
+    // `-` for stdout
+    pub y4m: Option<String>,
+    pub svg: Option<String>,
+    pub svg_arrows: bool,
+    pub term: Option<term::Style>,
+    // Columns and rows, asked to the terminal if None
+    pub term_size: Option<(usize, usize)>,
+    // Most terminal refreshes per second
+    pub fps: f32,
+    // Agent data dumps
+    pub vtk: Option<String>,
+    pub csv: Option<String>,
+    pub npy: Option<String>,
+    // Fluid surface contours as JSON
+    pub contours: Option<String>,
+    // JSON lines of metrics, `-` for stdout
+    pub metrics: Option<String>,
+    pub metrics_every: u64,
+}
+
+fn number<T: std::str::FromStr>(name: &str, v: String) -> Result<T, String> {
+    v.parse().map_err(|_| format!("{} needs a number", name))
+}
+
+fn size(name: &str, v: String) -> Result<(f32, f32), String> {
+    let err = || format!("{} needs WxH, got '{}'", name, v);
+    let mut it = v.split('x').map(|n| n.parse::<u32>());
+    match (it.next(), it.next(), it.next()) {
+        (Some(Ok(w)), Some(Ok(h)), None) if w > 0 && h > 0 => Ok((w as f32, h as f32)),
+        _ => Err(err()),
+    }
+}
+
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut a = Args {
+        seed: None,
+        record: Some(RECORD_FILE.to_string()),
+        replay: None,
+        headless: false,
+        steps: 1000,
+        every: 1,
+        size: (800.0, 800.0),
+        latex_div: LATEX_DIV,
+        resolution: None,
+        png: None,
+        y4m: None,
+        svg: None,
+        svg_arrows: false,
+        term: None,
+        term_size: None,
+        fps: 15.0,
+        vtk: None,
+        csv: None,
+        npy: None,
+        contours: None,
+        metrics: None,
+        metrics_every: metrics::EVERY,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--seed" => a.seed = Some(number("--seed", value()?)?),
+            "--record" => a.record = Some(value()?),
+            "--no-record" => a.record = None,
+            "--replay" => a.replay = Some(value()?),
+            "--headless" => a.headless = true,
+            "--steps" => a.steps = number("--steps", value()?)?,
+            "--every" => a.every = number("--every", value()?)?,
+            "--size" => a.size = size("--size", value()?)?,
+            "--latex-div" => a.latex_div = number("--latex-div", value()?)?,
+            "--resolution" => a.resolution = Some(size("--resolution", value()?)?),
+            "--png" => a.png = Some(value()?),
+            "--y4m" => a.y4m = Some(value()?),
+            "--svg" => a.svg = Some(value()?),
+            "--svg-arrows" => a.svg_arrows = true,
+            "--term" => a.term = Some(term::Style::parse(&value()?)?),
+            "--term-size" => {
+                let (c, r) = size("--term-size", value()?)?;
+                a.term_size = Some((c as usize, r as usize));
+            },
+            "--fps" => a.fps = number("--fps", value()?)?,
+            "--vtk" => a.vtk = Some(value()?),
+            "--csv" => a.csv = Some(value()?),
+            "--npy" => a.npy = Some(value()?),
+            "--contours" => a.contours = Some(value()?),
+            "--metrics" => a.metrics = Some(value()?),
+            "--metrics-every" => a.metrics_every = number("--metrics-every", value()?)?,
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    if a.latex_div.is_nan() || a.latex_div < 1.0 {
+        return Err("--latex-div must be at least 1".to_string());
+    }
+    if a.fps <= 0.0 {
+        return Err("--fps must be above 0".to_string());
+    }
+    if a.every == 0 || a.metrics_every == 0 {
+        return Err("--every and --metrics-every must be at least 1".to_string());
+    }
+    if a.headless && a.replay.is_some() {
+        return Err("--replay needs a window".to_string());
+    }
+    // Their output would be interleaved into one unreadable stream
+    let stdout = [a.y4m.as_deref() == Some("-"), a.metrics.as_deref() == Some("-"), a.term.is_some()];
+    if stdout.iter().filter(|x| **x).count() > 1 {
+        return Err("only one of --y4m -, --metrics - and --term can write to stdout".to_string());
+    }
+    // A replay is not recorded again, nor is a headless run
+    if a.replay.is_some() || a.headless {
+        a.record = None;
+    }
+    Ok(a)
+}
@@ -0,0 +1,133 @@
+use serde_json::json;
+use crate::utils;
+
+// Real seconds of backlog we are willing to catch up in a single frame,
+// anything above is dropped so a slow frame can't snowball.
+const MAX_FRAME_TIME: f64 = 0.1;
+
+pub struct Clock {
+    // Simulated time advanced by one step
+    pub dt: f32,
+    // Target simulation steps per real second
+    pub rate: f32,
+    // Slow motion factor applied to the real time fed to the accumulator
+    pub time_scale: f32,
+    pub paused: bool,
+    pub steps: u64,
+    pub time: f64,
+    acc: f64,
+    last: f64,
+    queued: usize,
+}
+
+impl Clock {
+    pub fn new(dt: f32, rate: f32) -> Clock {
+        Clock {
+            dt,
+            rate,
+            time_scale: 1.0,
+            paused: false,
+            steps: 0,
+            time: 0.0,
+            acc: 0.0,
+            last: utils::now(),
+            queued: 0,
+        }
+    }
+
+    // Consume the real time elapsed since the last call and return how many
+    // steps have to be simulated to catch up with it.
+    pub fn advance(&mut self) -> usize {
+        let now = utils::now();
+        let elapsed = (now - self.last).min(MAX_FRAME_TIME);
+        self.last = now;
+
+        if self.paused {
+            let n = self.queued;
+            self.queued = 0;
+            return n;
+        }
+
+        let step_time = 1.0 / self.rate as f64;
+        self.acc += elapsed * self.time_scale as f64;
+        let n = (self.acc / step_time) as usize;
+        self.acc -= n as f64 * step_time;
+        n
+    }
+
+    // Must be called once for every simulated step
+    pub fn tick(&mut self) {
+        self.steps += 1;
+        self.time += self.dt as f64;
+    }
+
+    // How far we are between the last simulated step and the next one,
+    // used to interpolate positions when rendering.
+    pub fn alpha(&self) -> f32 {
+        if self.paused {
+            return 1.0;
+        }
+        ((self.acc * self.rate as f64) as f32).clamp(0.0, 1.0)
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.acc = 0.0;
+    }
+
+    // Pause (if needed) and run exactly one step on the next frame
+    pub fn step_once(&mut self) {
+        self.paused = true;
+        self.queued += 1;
+    }
+}
+
+// Splits a step into substeps so that no particle travels more than a
+// fraction of the smallest view range, which would let it tunnel through
+// its neighbours.
+pub struct Adaptive {
+    pub enabled: bool,
+    // Max fraction of the view range covered thanks to the velocity
+    pub cfl: f32,
+    // Max fraction of the view range covered thanks to the acceleration
+    pub acc_limit: f32,
+    pub max_substeps: usize,
+    pub last_dt: f32,
+    pub last_substeps: usize,
+    // The last step wanted more than `max_substeps`
+    pub capped: bool,
+}
+
+impl Adaptive {
+    pub fn new() -> Adaptive {
+        Adaptive {
+            enabled: false,
+            cfl: 0.5,
+            acc_limit: 0.25,
+            max_substeps: 16,
+            last_dt: 0.0,
+            last_substeps: 1,
+            capped: false,
+        }
+    }
+
+    pub fn substeps(&mut self, dt: f32, max_vel: f32, max_acc: f32, min_range: f32) -> usize {
+        let mut h = dt;
+        if max_vel > 0.0 {
+            h = h.min(self.cfl * min_range / max_vel);
+        }
+        if max_acc > 0.0 {
+            h = h.min((2.0 * self.acc_limit * min_range / max_acc).sqrt());
+        }
+        let wanted = (dt / h).ceil() as usize;
+        let n = wanted.clamp(1, self.max_substeps);
+        // Particles may tunnel meanwhile, said once per run of capped steps
+        if wanted > self.max_substeps && !self.capped {
+            utils::log("substeps_capped", json!({ "wanted": wanted, "max": self.max_substeps, "dt": dt }));
+        }
+        self.capped = wanted > self.max_substeps;
+        self.last_dt = dt / n as f32;
+        self.last_substeps = n;
+        n
+    }
+}
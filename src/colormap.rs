@@ -0,0 +1,198 @@
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::nalgebra::Point2;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::ag::Agent;
+use crate::latex::Latex2D;
+use crate::sim::Sim;
+
+const MARGIN: f32 = 10.0;
+const BAR: f32 = 200.0;
+const STEPS: usize = 50;
+
+// What the agents are coloured by
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    // Their own colour, with the opacity from the speed
+    Color,
+    Speed,
+    Neighbours,
+    // Neighbours per unit of area of the view range
+    Density,
+    // Density relative to the mean one, minus one
+    Pressure,
+    // Curl of the velocity around the agent, positive counterclockwise
+    Vorticity,
+    ViewRange,
+    PosW,
+    VelW,
+    Drag,
+    Weirdness,
+}
+
+const MODES: &[Mode] = &[
+    Mode::Color, Mode::Speed, Mode::Neighbours, Mode::Density, Mode::Pressure, Mode::Vorticity,
+    Mode::ViewRange, Mode::PosW, Mode::VelW, Mode::Drag, Mode::Weirdness,
+];
+
+impl Mode {
+    pub fn next(self) -> Mode {
+        let i = MODES.iter().position(|m| *m == self).unwrap_or(0);
+        MODES[(i + 1) % MODES.len()]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Map {
+    Viridis,
+    Magma,
+    Inferno,
+    Plasma,
+    Cividis,
+}
+
+const MAPS: &[Map] = &[Map::Viridis, Map::Magma, Map::Inferno, Map::Plasma, Map::Cividis];
+
+// Evenly spaced samples of the matplotlib colour maps
+const VIRIDIS: [u32; 9] = [0x440154, 0x472d7b, 0x3b528b, 0x2c728e, 0x21918c, 0x28ae80, 0x5ec962, 0xaddc30, 0xfde725];
+const MAGMA: [u32; 9] = [0x000004, 0x1c1044, 0x4f127b, 0x812581, 0xb5367a, 0xe55064, 0xfb8761, 0xfec287, 0xfcfdbf];
+const INFERNO: [u32; 9] = [0x000004, 0x1f0c48, 0x550f6d, 0x88226a, 0xba3655, 0xe35933, 0xf98e09, 0xf9cb35, 0xfcffa4];
+const PLASMA: [u32; 9] = [0x0d0887, 0x4c02a1, 0x7e03a8, 0xa92395, 0xcc4778, 0xe56b5d, 0xf89540, 0xfdc527, 0xf0f921];
+const CIVIDIS: [u32; 9] = [0x00224e, 0x123570, 0x3b496c, 0x575d6d, 0x707173, 0x8a8779, 0xa69d75, 0xc4b56c, 0xfee838];
+
+impl Map {
+    pub fn next(self) -> Map {
+        let i = MAPS.iter().position(|m| *m == self).unwrap_or(0);
+        MAPS[(i + 1) % MAPS.len()]
+    }
+
+    // Colour at `t` in 0..1
+    pub fn sample(self, t: f32) -> [f32; 3] {
+        let table = match self {
+            Map::Viridis => &VIRIDIS,
+            Map::Magma => &MAGMA,
+            Map::Inferno => &INFERNO,
+            Map::Plasma => &PLASMA,
+            Map::Cividis => &CIVIDIS,
+        };
+        let x = t.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+        let i = (x as usize).min(table.len() - 2);
+        let f = x - i as f32;
+        let c = |v: u32, shift: u32| ((v >> shift) & 0xff) as f32 / 255.0;
+        let mut rgb = [0.0; 3];
+        for (k, shift) in [16, 8, 0].iter().enumerate() {
+            rgb[k] = c(table[i], *shift) * (1.0 - f) + c(table[i + 1], *shift) * f;
+        }
+        rgb
+    }
+}
+
+// The value of every agent for `mode`, None for Mode::Color
+pub fn values(sim: &Sim, latex: &Latex2D<Agent>, mode: Mode) -> Option<Vec<f32>> {
+    let density = |x: &Agent| x.s_in_range as f32 / (std::f32::consts::PI * x.view_range * x.view_range);
+    Some(match mode {
+        Mode::Color => return None,
+        Mode::Speed => sim.agents.iter().map(|x| x.s_vel).collect(),
+        Mode::Neighbours => sim.agents.iter().map(|x| x.s_in_range as f32).collect(),
+        Mode::Density => sim.agents.iter().map(density).collect(),
+        Mode::Pressure => {
+            let d: Vec<f32> = sim.agents.iter().map(density).collect();
+            let mean = d.iter().sum::<f32>() / d.len().max(1) as f32;
+            d.iter().map(|v| if mean > 0.0 { v / mean - 1.0 } else { 0.0 }).collect()
+        },
+        Mode::Vorticity => {
+            let (pw, ph) = sim.fields.period(sim.w, sim.h);
+            sim.agents.par_iter().map(|x| {
+                let mut curl = 0.0;
+                let mut n = 0;
+                for o in latex.get((x.pos.x, x.pos.y), x.view_range).iter().filter(|o| o.id != x.id) {
+                    let p = x.pos.rel(&o.pos, pw, ph);
+                    let (rx, ry) = (p.x - x.pos.x, p.y - x.pos.y);
+                    let d2 = rx * rx + ry * ry;
+                    if d2 <= 0.0 || d2 > x.view_range * x.view_range { continue; }
+                    let (vx, vy) = (o.vel.x - x.vel.x, o.vel.y - x.vel.y);
+                    // Screen y points down, flip it so positive is counterclockwise
+                    curl -= (rx * vy - ry * vx) / d2;
+                    n += 1;
+                }
+                if n > 0 { curl / n as f32 } else { 0.0 }
+            }).collect()
+        },
+        Mode::ViewRange => sim.agents.iter().map(|x| x.view_range).collect(),
+        Mode::PosW => sim.agents.iter().map(|x| x.pos_w).collect(),
+        Mode::VelW => sim.agents.iter().map(|x| x.vel_w).collect(),
+        Mode::Drag => sim.agents.iter().map(|x| x.drag).collect(),
+        Mode::Weirdness => sim.agents.iter().map(|x| x.weirdness).collect(),
+    })
+}
+
+// 2nd and 98th percentiles, so a few outliers don't wash out the rest
+pub fn auto_range(values: &[f32]) -> (f32, f32) {
+    let mut v: Vec<f32> = values.iter().cloned().filter(|x| x.is_finite()).collect();
+    if v.is_empty() { return (0.0, 1.0); }
+    v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let at = |q: f32| v[((v.len() - 1) as f32 * q).round() as usize];
+    let (min, max) = (at(0.02), at(0.98));
+    if max - min < 1e-6 { (min - 0.5, max + 0.5) } else { (min, max) }
+}
+
+#[derive(Clone, Debug)]
+pub struct Coloring {
+    pub mode: Mode,
+    pub map: Map,
+    // Values mapped to the ends of the colour map, taken from the agents if None
+    pub range: Option<(f32, f32)>,
+    // Range used by the last call to `colors`, for the legend
+    pub shown: (f32, f32),
+}
+
+impl Default for Coloring {
+    fn default() -> Coloring {
+        Coloring { mode: Mode::Color, map: Map::Viridis, range: None, shown: (0.0, 1.0) }
+    }
+}
+
+impl Coloring {
+    // Opaque colour of every agent, None when they keep their own
+    pub fn colors(&mut self, sim: &Sim, latex: &Latex2D<Agent>) -> Option<Vec<[f32; 4]>> {
+        let values = values(sim, latex, self.mode)?;
+        self.shown = self.range.unwrap_or_else(|| auto_range(&values));
+        let (min, max) = self.shown;
+        Some(values.iter().map(|v| {
+            let [r, g, b] = self.map.sample((v - min) / (max - min));
+            [r, g, b, 1.0]
+        }).collect())
+    }
+
+    // Colour bar with the range in the bottom right corner
+    pub fn draw_legend(&self, ctx: &mut Context) -> GameResult<()> {
+        if self.mode == Mode::Color { return Ok(()); }
+        let (w, h) = graphics::drawable_size(ctx);
+        let (x0, y0) = (w - MARGIN - BAR, h - MARGIN - 30.0);
+        let mut mb = graphics::MeshBuilder::new();
+        mb.rectangle(graphics::DrawMode::fill(),
+            graphics::Rect::new(x0 - 5.0, y0 - 22.0, BAR + 10.0, 57.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.6));
+        let step = BAR / STEPS as f32;
+        for i in 0..STEPS {
+            let [r, g, b] = self.map.sample(i as f32 / (STEPS - 1) as f32);
+            mb.rectangle(graphics::DrawMode::fill(),
+                graphics::Rect::new(x0 + i as f32 * step, y0, step + 0.5, 12.0),
+                graphics::Color::new(r, g, b, 1.0));
+        }
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::new())?;
+
+        let range = if self.range.is_some() { "fixed" } else { "auto" };
+        let title = format!("{:?} ({:?}, {})", self.mode, self.map, range).to_lowercase();
+        let max = graphics::Text::new(format!("{:.3}", self.shown.1));
+        let max_w = max.width(ctx) as f32;
+        graphics::draw(ctx, &graphics::Text::new(title), graphics::DrawParam::new().dest(Point2::new(x0, y0 - 18.0)))?;
+        graphics::draw(ctx, &graphics::Text::new(format!("{:.3}", self.shown.0)), graphics::DrawParam::new().dest(Point2::new(x0, y0 + 15.0)))?;
+        graphics::draw(ctx, &max, graphics::DrawParam::new().dest(Point2::new(x0 + BAR - max_w, y0 + 15.0)))?;
+        Ok(())
+    }
+}
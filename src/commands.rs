@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use ggez::input::keyboard::{KeyCode, KeyMods};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Help,
+    Hud,
+    Trace,
+    ExportSvg,
+    Undo,
+    ScrubBack,
+    ScrubForward,
+    Rewind,
+    Branch,
+    Panel,
+    Graph,
+    ColorMode,
+    Flow,
+    Surface,
+    SavePreset,
+    LoadPreset,
+    Pause,
+    Step,
+    SlowMotion,
+    Fast,
+    Integrator,
+    Adaptive,
+    GravityPreset,
+    GravityForce,
+    Editor,
+    SaveWells,
+    LoadWells,
+    Fields,
+    PaintRed,
+    PaintGreen,
+    PaintBlue,
+    BrushTool,
+    BrushSmaller,
+    BrushBigger,
+    BrushWeaker,
+    BrushStronger,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arg {
+    None,
+    // The command waits for a digit, and keeps taking digits until another
+    // command is pressed
+    Digit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Binding {
+    pub key: KeyCode,
+    pub mods: KeyMods,
+}
+
+pub struct Command {
+    pub action: Action,
+    // Name used in the config file
+    pub name: &'static str,
+    pub description: &'static str,
+    pub arg: Arg,
+    pub bindings: Vec<Binding>,
+}
+
+pub struct Commands {
+    pub list: Vec<Command>,
+    pending: Option<Action>,
+}
+
+// (action, name, arg, default bindings, description)
+const TABLE: &[(Action, &str, Arg, &[&str], &str)] = &[
+    (Action::Quit, "quit", Arg::None, &["Escape"], "Quit"),
+    (Action::Help, "help", Arg::None, &["H", "F1"], "Show or hide this help"),
+    (Action::Hud, "hud", Arg::None, &["Tab"], "Cycle HUD verbosity"),
+    (Action::Trace, "export_trace", Arg::None, &["F12"], "Save a Chrome trace of the last frames"),
+    (Action::ExportSvg, "export_svg", Arg::None, &["F11"], "Save the agents as an SVG figure, with velocities on Shift"),
+    (Action::Undo, "undo", Arg::None, &["Ctrl+Z"], "Undo the last edit, or go back a snapshot"),
+    (Action::ScrubBack, "scrub_back", Arg::None, &["Left"], "Pause and show the previous snapshot"),
+    (Action::ScrubForward, "scrub_forward", Arg::None, &["Right"], "Pause and show the next snapshot"),
+    (Action::Rewind, "rewind", Arg::None, &["Home"], "Pause and show the oldest snapshot"),
+    (Action::Branch, "branch", Arg::None, &["Return"], "Resume from the snapshot shown, dropping its future"),
+    (Action::Panel, "panel", Arg::None, &["O"], "Show or hide the parameter panel"),
+    (Action::Graph, "graph", Arg::None, &["M"], "Show or hide the metrics graph"),
+    (Action::ColorMode, "color_mode", Arg::None, &["V"], "Colour agents by speed, density, ... (Shift: colour map)"),
+    (Action::Flow, "flow", Arg::None, &["W"], "Show velocity arrows, streamlines or LIC (Shift: grid density)"),
+    (Action::Surface, "surface", Arg::None, &["J"], "Draw the fluid surface instead of dots (Shift: save contours)"),
+    (Action::SavePreset, "save_preset", Arg::None, &["Ctrl+S"], "Save parameters as a preset"),
+    (Action::LoadPreset, "load_preset", Arg::None, &["Ctrl+O"], "Load the saved preset"),
+    (Action::Pause, "pause", Arg::None, &["Space"], "Pause or resume"),
+    (Action::Step, "step", Arg::None, &["Period"], "Advance a single step"),
+    (Action::SlowMotion, "slow_motion", Arg::Digit, &["S"], "Slow motion, 1 real time .. 0 a tenth"),
+    (Action::Fast, "fast", Arg::Digit, &["F"], "Simulation speed"),
+    (Action::Integrator, "integrator", Arg::None, &["I"], "Cycle integrator"),
+    (Action::Adaptive, "adaptive_dt", Arg::None, &["D"], "Toggle adaptive timestep"),
+    (Action::GravityPreset, "gravity_preset", Arg::Digit, &["Z"], "Load a gravity well layout"),
+    (Action::GravityForce, "gravity_force", Arg::Digit, &["X"], "Strength of all wells"),
+    (Action::Editor, "well_editor", Arg::None, &["E"], "Toggle the well editor"),
+    (Action::SaveWells, "save_wells", Arg::None, &["K"], "Save wells"),
+    (Action::LoadWells, "load_wells", Arg::None, &["L"], "Load wells"),
+    (Action::Fields, "fields", Arg::Digit, &["C"], "Toggle gravity, wind, vortices, noise, walls (1-5)"),
+    (Action::PaintRed, "paint_red", Arg::None, &["R", "A"], "Paint random agents red (x10 with Shift+Ctrl)"),
+    (Action::PaintGreen, "paint_green", Arg::None, &["G"], "Paint random agents green (x10 with Shift+Ctrl)"),
+    (Action::PaintBlue, "paint_blue", Arg::None, &["B"], "Paint random agents blue (x10 with Shift+Ctrl)"),
+    (Action::BrushTool, "brush_tool", Arg::Digit, &["T"], "Brush tool 1-8, 9 falloff, 0 paint colour"),
+    (Action::BrushSmaller, "brush_smaller", Arg::None, &["LBracket"], "Smaller brush"),
+    (Action::BrushBigger, "brush_bigger", Arg::None, &["RBracket"], "Bigger brush"),
+    (Action::BrushWeaker, "brush_weaker", Arg::None, &["Minus"], "Weaker brush"),
+    (Action::BrushStronger, "brush_stronger", Arg::None, &["Equals"], "Stronger brush"),
+];
+
+const KEYS: &[KeyCode] = &[
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::Escape, KeyCode::Space, KeyCode::Tab, KeyCode::Back, KeyCode::Return,
+    KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+    KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down,
+    KeyCode::Period, KeyCode::Comma, KeyCode::Minus, KeyCode::Equals, KeyCode::Slash,
+    KeyCode::Backslash, KeyCode::Semicolon, KeyCode::Apostrophe, KeyCode::Grave,
+    KeyCode::LBracket, KeyCode::RBracket,
+];
+
+pub fn digit(key: KeyCode) -> Option<usize> {
+    match key {
+        KeyCode::Key0 => Some(0),
+        KeyCode::Key1 => Some(1),
+        KeyCode::Key2 => Some(2),
+        KeyCode::Key3 => Some(3),
+        KeyCode::Key4 => Some(4),
+        KeyCode::Key5 => Some(5),
+        KeyCode::Key6 => Some(6),
+        KeyCode::Key7 => Some(7),
+        KeyCode::Key8 => Some(8),
+        KeyCode::Key9 => Some(9),
+        _ => None,
+    }
+}
+
+// Parses bindings like "B", "Ctrl+Shift+B" or "F1", key names are the
+// ones of ggez `KeyCode`
+pub fn parse_binding(s: &str) -> Result<Binding, String> {
+    let mut mods = KeyMods::NONE;
+    let parts: Vec<&str> = s.split('+').map(|x| x.trim()).collect();
+    let (key, modifiers) = parts.split_last().ok_or_else(|| format!("empty binding '{}'", s))?;
+    for m in modifiers {
+        mods |= match m.to_lowercase().as_str() {
+            "shift" => KeyMods::SHIFT,
+            "ctrl" => KeyMods::CTRL,
+            "alt" => KeyMods::ALT,
+            "logo" => KeyMods::LOGO,
+            _ => return Err(format!("unknown modifier '{}' in '{}'", m, s)),
+        };
+    }
+    let key = KEYS.iter()
+        .find(|k| format!("{:?}", k).eq_ignore_ascii_case(key))
+        .ok_or_else(|| format!("unknown key '{}' in '{}'", key, s))?;
+    Ok(Binding { key: *key, mods })
+}
+
+pub fn binding_name(b: &Binding) -> String {
+    let mut s = String::new();
+    if b.mods.contains(KeyMods::CTRL) { s += "Ctrl+"; }
+    if b.mods.contains(KeyMods::ALT) { s += "Alt+"; }
+    if b.mods.contains(KeyMods::SHIFT) { s += "Shift+"; }
+    if b.mods.contains(KeyMods::LOGO) { s += "Logo+"; }
+    s + &format!("{:?}", b.key)
+}
+
+impl Commands {
+    pub fn new() -> Commands {
+        Commands {
+            list: TABLE.iter().map(|(action, name, arg, keys, description)| Command {
+                action: *action,
+                name,
+                description,
+                arg: *arg,
+                bindings: keys.iter().map(|k| parse_binding(k).expect("bad default binding")).collect(),
+            }).collect(),
+            pending: None,
+        }
+    }
+
+    pub fn get(&self, action: Action) -> &Command {
+        self.list.iter().find(|c| c.action == action).expect("command not in table")
+    }
+
+    pub fn pending(&self) -> Option<Action> {
+        self.pending
+    }
+
+    // Replace the bindings of the commands named in `bindings`, the others
+    // keep their defaults. Returns the entries that were rejected.
+    pub fn remap(&mut self, bindings: &HashMap<String, Vec<String>>) -> Vec<String> {
+        let mut errors = vec![];
+        for (name, keys) in bindings.iter() {
+            let cmd = match self.list.iter_mut().find(|c| c.name == name) {
+                Some(c) => c,
+                None => {
+                    errors.push(format!("unknown command '{}'", name));
+                    continue;
+                }
+            };
+            let parsed: Result<Vec<Binding>, String> = keys.iter().map(|k| parse_binding(k)).collect();
+            match parsed {
+                Ok(b) => cmd.bindings = b,
+                Err(e) => errors.push(e),
+            }
+        }
+        errors
+    }
+
+    // Command bound to a key, the most specific binding wins, so Ctrl+B can
+    // differ from B
+    pub fn lookup(&self, key: KeyCode, mods: KeyMods) -> Option<Action> {
+        self.list.iter()
+            .flat_map(|c| c.bindings.iter().map(move |b| (c, b)))
+            .filter(|(_, b)| b.key == key && mods.contains(b.mods))
+            .max_by_key(|(_, b)| b.mods.bits().count_ones())
+            .map(|(c, _)| c.action)
+    }
+
+    // Resolve a key press into an action, and its digit for commands that
+    // take one
+    pub fn press(&mut self, key: KeyCode, mods: KeyMods) -> Option<(Action, Option<usize>)> {
+        let found = self.lookup(key, mods).map(|a| (a, self.get(a).arg));
+
+        match found {
+            Some((action, Arg::None)) => Some((action, None)),
+            Some((action, Arg::Digit)) => {
+                self.pending = Some(action);
+                None
+            }
+            None => match (self.pending, digit(key)) {
+                (Some(action), Some(d)) => Some((action, Some(d))),
+                _ => None,
+            },
+        }
+    }
+
+    // One line per command, `value` can add the current state of a command
+    pub fn help<F>(&self, value: F) -> Vec<String>
+    where F: Fn(Action) -> Option<String> {
+        self.list.iter().map(|c| {
+            let keys: Vec<String> = c.bindings.iter().map(binding_name).collect();
+            let keys = keys.join(" / ") + if c.arg == Arg::Digit { " <0-9>" } else { "" };
+            match value(c.action) {
+                Some(v) => format!("{:<22} {}  [{}]", keys, c.description, v),
+                None => format!("{:<22} {}", keys, c.description),
+            }
+        }).collect()
+    }
+}
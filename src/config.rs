@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use toml::value::{Table, Value};
+use crate::colormap::{Coloring, Map, Mode};
+use crate::flow::{Flow, Overlay};
+use crate::surface::Surface;
+use crate::gravity::Well;
+
+pub const CONFIG_FILE: &str = "fluid.toml";
+
+// Seconds between two checks of the file modification time
+const POLL_EVERY: f64 = 0.5;
+
+// Optional settings read from CONFIG_FILE, at startup and again whenever the
+// file changes. Sections that are missing leave the running state alone, and
+// `params` and `fields` may list only the keys to change, e.g.
+//
+//     [bindings]
+//     paint_red = ["R"]
+//     pause = ["Space", "P"]
+//
+//     [params]
+//     weirdness = 1.5
+//
+//     [fields.boundary]
+//     kind = "walls"
+//     restitution = 0.8
+//
+//     [render]
+//     hud = 2
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub bindings: HashMap<String, Vec<String>>,
+    pub params: Option<Value>,
+    pub fields: Option<Value>,
+    pub wells: Option<Vec<Well>>,
+    pub render: Option<Render>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Render {
+    pub hud: Option<usize>,
+    pub panel: Option<bool>,
+    pub help: Option<bool>,
+    pub graph: Option<bool>,
+    // Plots shown by the graph, e.g. ["speed", "energy"]
+    pub graph_metrics: Option<Vec<String>>,
+    // What colours the agents, e.g. "speed", and with which colour map
+    pub color_mode: Option<Mode>,
+    pub color_map: Option<Map>,
+    // [min, max], or [] to follow the values
+    pub color_range: Option<Vec<f32>>,
+    // Velocity overlay, grid spacing in pixels and length in cells
+    pub flow: Option<Overlay>,
+    pub flow_cell: Option<f32>,
+    pub flow_length: Option<f32>,
+    // Fluid surface instead of dots: grid spacing, reach of an agent and
+    // level of the edge
+    pub surface: Option<bool>,
+    pub surface_cell: Option<f32>,
+    pub surface_radius: Option<f32>,
+    pub surface_iso: Option<f32>,
+}
+
+impl Render {
+    // The keys of the `render` section of `table` that are in `changed`, so
+    // what was toggled since from the keyboard stays as it is
+    pub fn changed(table: &Table, changed: &[String]) -> Result<Option<Render>, String> {
+        let section = match table.get("render") {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let (patch, names) = pick(section, "render", changed);
+        if names.is_empty() { return Ok(None); }
+        patch.try_into().map(Some).map_err(|e: toml::de::Error| e.to_string())
+    }
+
+    // `current` with the colour keys of this section applied
+    pub fn coloring(&self, current: &Coloring) -> Result<Coloring, String> {
+        let mut c = current.clone();
+        if let Some(m) = self.color_mode { c.mode = m; }
+        if let Some(m) = self.color_map { c.map = m; }
+        match self.color_range.as_deref() {
+            None => {},
+            Some([]) => c.range = None,
+            Some([min, max]) if min < max => c.range = Some((*min, *max)),
+            Some(_) => return Err("render.color_range must be [] or [min, max] with min < max".to_string()),
+        }
+        Ok(c)
+    }
+
+    // `current` with the flow keys of this section applied
+    pub fn flow(&self, current: &Flow) -> Result<Flow, String> {
+        let mut f = current.clone();
+        if let Some(o) = self.flow { f.overlay = o; }
+        match self.flow_cell {
+            Some(v) if v < 4.0 => return Err("render.flow_cell must be at least 4".to_string()),
+            Some(v) => f.cell = v,
+            None => {},
+        }
+        match self.flow_length {
+            Some(v) if v <= 0.0 => return Err("render.flow_length must be positive".to_string()),
+            Some(v) => f.length = v,
+            None => {},
+        }
+        Ok(f)
+    }
+
+    // `current` with the surface keys of this section applied
+    pub fn surface(&self, current: &Surface) -> Result<Surface, String> {
+        let mut s = current.clone();
+        if let Some(v) = self.surface { s.enabled = v; }
+        for (key, value, field, min) in [
+            ("surface_cell", self.surface_cell, &mut s.cell, 1.0),
+            ("surface_radius", self.surface_radius, &mut s.radius, 0.0),
+            ("surface_iso", self.surface_iso, &mut s.iso, 0.0),
+        ] {
+            match value {
+                Some(v) if v <= min => return Err(format!("render.{} must be above {}", key, min)),
+                Some(v) => *field = v,
+                None => {},
+            }
+        }
+        Ok(s)
+    }
+}
+
+impl Config {
+    pub fn parse(table: &Table) -> Result<Config, String> {
+        Value::Table(table.clone()).try_into().map_err(|e: toml::de::Error| e.to_string())
+    }
+}
+
+// Returns `current` with the keys of `patch` replaced, so partial sections
+// keep the values that are not mentioned
+pub fn merge<T: Serialize + DeserializeOwned>(current: &T, patch: &Value) -> Result<T, String> {
+    let mut value = Value::try_from(current).map_err(|e| e.to_string())?;
+    merge_value(&mut value, patch);
+    value.try_into().map_err(|e: toml::de::Error| e.to_string())
+}
+
+fn merge_value(a: &mut Value, b: &Value) {
+    match (a, b) {
+        // Tagged enums change shape with their kind, so they are replaced whole
+        (Value::Table(a), Value::Table(b)) if !b.contains_key("kind") => {
+            for (k, v) in b.iter() {
+                match a.get_mut(k) {
+                    Some(x) => merge_value(x, v),
+                    None => { a.insert(k.clone(), v.clone()); },
+                }
+            }
+        }
+        (a, b) => *a = b.clone(),
+    }
+}
+
+// The keys of `section`, found at `prefix` in the file, whose dotted path is
+// in `changed`, and their paths within the section. Merging only these keeps
+// the values changed in the meantime by other means, e.g. the panel.
+pub fn pick(section: &Value, prefix: &str, changed: &[String]) -> (Value, Vec<String>) {
+    let mut patch = Value::Table(Table::new());
+    let mut names = vec![];
+    // A section that was added or removed as a whole changes all its keys
+    let mut keys: Vec<String> = changed.iter().filter_map(|k| k.strip_prefix(&format!("{}.", prefix))).map(String::from).collect();
+    if changed.iter().any(|k| k == prefix) {
+        if let Some(t) = section.as_table() { keys.extend(changed_keys(&Table::new(), t)); }
+    }
+    for key in keys.iter() {
+        let path: Vec<&str> = key.split('.').collect();
+        // Removed keys keep their current value
+        let value = match path.iter().try_fold(section, |v, k| v.get(*k)) {
+            Some(v) => v.clone(),
+            None => continue,
+        };
+        let mut t = &mut patch;
+        for k in path[..path.len() - 1].iter() {
+            t = t.as_table_mut().unwrap().entry(k.to_string()).or_insert_with(|| Value::Table(Table::new()));
+        }
+        t.as_table_mut().unwrap().insert(path[path.len() - 1].to_string(), value);
+        names.push(key.to_string());
+    }
+    (patch, names)
+}
+
+// Dotted paths of the leaves that differ between two tables
+pub fn changed_keys(a: &Table, b: &Table) -> Vec<String> {
+    let mut keys = vec![];
+    diff("", &Value::Table(a.clone()), &Value::Table(b.clone()), &mut keys);
+    keys
+}
+
+fn diff(path: &str, a: &Value, b: &Value, keys: &mut Vec<String>) {
+    let join = |k: &str| if path.is_empty() { k.to_string() } else { format!("{}.{}", path, k) };
+    match (a, b) {
+        (Value::Table(a), Value::Table(b)) => {
+            let mut names: Vec<&String> = a.keys().chain(b.keys()).collect();
+            names.sort();
+            names.dedup();
+            for k in names {
+                match (a.get(k), b.get(k)) {
+                    (Some(x), Some(y)) => diff(&join(k), x, y, keys),
+                    _ => keys.push(join(k)),
+                }
+            }
+        }
+        (a, b) if a != b => keys.push(path.to_string()),
+        _ => {},
+    }
+}
+
+// Notices when the config file is written and tells which keys changed since
+// the last version that was accepted
+pub struct Watcher {
+    pub path: String,
+    modified: Option<SystemTime>,
+    checked: f64,
+    accepted: Table,
+}
+
+impl Watcher {
+    pub fn new(path: &str) -> Watcher {
+        Watcher {
+            path: path.to_string(),
+            modified: None,
+            checked: 0.0,
+            accepted: Table::new(),
+        }
+    }
+
+    // None while the file is unchanged, otherwise its contents and the keys
+    // that differ from the accepted ones
+    pub fn poll(&mut self, now: f64) -> Option<Result<(Table, Vec<String>), String>> {
+        if now < self.checked + POLL_EVERY { return None; }
+        self.checked = now;
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if self.modified == Some(modified) { return None; }
+        self.modified = Some(modified);
+        let table = std::fs::read_to_string(&self.path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str::<Table>(&text).map_err(|e| e.to_string()));
+        Some(table.map(|t| {
+            let keys = changed_keys(&self.accepted, &t);
+            (t, keys)
+        }))
+    }
+
+    pub fn accept(&mut self, table: Table) {
+        self.accepted = table;
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use crate::ag::Agent;
+use crate::output::Sink;
+use crate::raster::Renderer;
+use crate::sim::Sim;
+
+type Scalar = (&'static str, fn(&Agent) -> f32);
+
+// Per agent values written next to position, velocity and colour
+const SCALARS: &[Scalar] = &[
+    ("view_range", |a| a.view_range),
+    ("pos_w", |a| a.pos_w),
+    ("vel_w", |a| a.vel_w),
+    ("drag", |a| a.drag),
+    ("s_in_range", |a| a.s_in_range as f32),
+    ("s_vel", |a| a.s_vel),
+];
+
+fn create(path: &str) -> Result<BufWriter<File>, String> {
+    File::create(path).map(BufWriter::new).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn write_csv(path: &str, agents: &[Agent]) -> Result<(), String> {
+    let mut f = create(path)?;
+    (|| {
+        write!(f, "id,x,y,vx,vy,r,g,b")?;
+        for (name, _) in SCALARS.iter() { write!(f, ",{}", name)?; }
+        writeln!(f)?;
+        for a in agents.iter() {
+            write!(f, "{},{},{},{},{},{},{},{}", a.id, a.pos.x, a.pos.y, a.vel.x, a.vel.y, a.color[0], a.color[1], a.color[2])?;
+            for (_, get) in SCALARS.iter() { write!(f, ",{}", get(a))?; }
+            writeln!(f)?;
+        }
+        f.flush()
+    })().map_err(|e| format!("{}: {}", path, e))
+}
+
+// Legacy ASCII VTK polydata, one vertex per agent
+pub fn write_vtk(path: &str, sim: &Sim) -> Result<(), String> {
+    let mut f = create(path)?;
+    let n = sim.agents.len();
+    (|| {
+        writeln!(f, "# vtk DataFile Version 3.0")?;
+        writeln!(f, "fluid step {} time {}", sim.clock.steps, sim.clock.time)?;
+        writeln!(f, "ASCII\nDATASET POLYDATA")?;
+        writeln!(f, "POINTS {} float", n)?;
+        for a in sim.agents.iter() { writeln!(f, "{} {} 0", a.pos.x, a.pos.y)?; }
+        writeln!(f, "VERTICES {} {}", n, n * 2)?;
+        for i in 0..n { writeln!(f, "1 {}", i)?; }
+
+        writeln!(f, "POINT_DATA {}", n)?;
+        writeln!(f, "SCALARS id int 1\nLOOKUP_TABLE default")?;
+        for a in sim.agents.iter() { writeln!(f, "{}", a.id)?; }
+        writeln!(f, "VECTORS velocity float")?;
+        for a in sim.agents.iter() { writeln!(f, "{} {} 0", a.vel.x, a.vel.y)?; }
+        // COLOR_SCALARS must stay in 0..1
+        writeln!(f, "COLOR_SCALARS color 3")?;
+        for a in sim.agents.iter() {
+            let c = |i: usize| a.color[i].clamp(0.0, 1.0);
+            writeln!(f, "{} {} {}", c(0), c(1), c(2))?;
+        }
+        for (name, get) in SCALARS.iter() {
+            writeln!(f, "SCALARS {} float 1\nLOOKUP_TABLE default", name)?;
+            for a in sim.agents.iter() { writeln!(f, "{}", get(a))?; }
+        }
+        f.flush()
+    })().map_err(|e| format!("{}: {}", path, e))
+}
+
+// ParaView collection listing every dump with its simulation time
+pub fn write_pvd(path: &str, entries: &[(f64, String)]) -> Result<(), String> {
+    let mut f = create(path)?;
+    (|| {
+        writeln!(f, r#"<?xml version="1.0"?>"#)?;
+        writeln!(f, r#"<VTKFile type="Collection" version="0.1">"#)?;
+        writeln!(f, "<Collection>")?;
+        for (time, file) in entries.iter() {
+            writeln!(f, r#"<DataSet timestep="{}" part="0" file="{}"/>"#, time, file)?;
+        }
+        writeln!(f, "</Collection>\n</VTKFile>")?;
+        f.flush()
+    })().map_err(|e| format!("{}: {}", path, e))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Vtk,
+    Csv,
+}
+
+// agents_00000.vtk, ... and agents.pvd, or agents_00000.csv, ... in `dir`
+pub struct Dump {
+    dir: String,
+    format: Format,
+    entries: Vec<(f64, String)>,
+}
+
+impl Dump {
+    pub fn new(dir: &str, format: Format) -> Result<Dump, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        Ok(Dump { dir: dir.to_string(), format, entries: vec![] })
+    }
+}
+
+impl Sink for Dump {
+    fn needs_canvas(&self) -> bool {
+        false
+    }
+
+    fn frame(&mut self, sim: &Sim, _renderer: &Renderer) -> Result<(), String> {
+        let n = self.entries.len();
+        match self.format {
+            Format::Csv => {
+                let file = format!("agents_{:05}.csv", n);
+                write_csv(&format!("{}/{}", self.dir, file), &sim.agents)?;
+                self.entries.push((sim.clock.time, file));
+            },
+            Format::Vtk => {
+                let file = format!("agents_{:05}.vtk", n);
+                write_vtk(&format!("{}/{}", self.dir, file), sim)?;
+                self.entries.push((sim.clock.time, file));
+                // Rewritten every time, so an interrupted run can still be opened
+                write_pvd(&format!("{}/agents.pvd", self.dir), &self.entries)?;
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use ggez::graphics;
+use crate::gravity::{self, Well};
+use crate::vec;
+
+// Strength change per wheel notch
+const WHEEL_STEP: f32 = 0.05;
+
+// Lets presenters place, move, tune and delete gravity wells with the mouse
+pub struct WellEditor {
+    pub enabled: bool,
+    pub selected: Option<usize>,
+    dragging: bool,
+}
+
+// Radius of the ring drawn around a well, grows with its strength
+pub fn ring_radius(w: &Well) -> f32 {
+    8.0 + 20.0 * w.strength.abs().sqrt()
+}
+
+impl WellEditor {
+    pub fn new() -> WellEditor {
+        WellEditor {
+            enabled: false,
+            selected: None,
+            dragging: false,
+        }
+    }
+
+    // Index of the well under `p`, wells are looked up where they are drawn
+    pub fn pick(wells: &[Well], time: f32, p: vec::Vec) -> Option<usize> {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, w) in wells.iter().enumerate() {
+            let d = w.at(time, p).pos.dist(&p);
+            if w.path != gravity::Path::Mouse && d < ring_radius(w) && (best.is_none() || best.unwrap().1 > d) {
+                best = Some((i, d));
+            }
+        }
+        best.map(|b| b.0)
+    }
+
+    // Grab the well under the cursor, or place a new one there
+    pub fn press(&mut self, wells: &mut Vec<Well>, time: f32, p: vec::Vec, strength: f32) {
+        self.selected = match WellEditor::pick(wells, time, p) {
+            Some(i) => Some(i),
+            None => {
+                wells.push(Well::new(p, strength));
+                Some(wells.len() - 1)
+            }
+        };
+        self.dragging = true;
+    }
+
+    pub fn drag(&mut self, wells: &mut [Well], d: vec::Vec) {
+        if !self.dragging { return; }
+        if let Some(w) = self.selected.and_then(|i| wells.get_mut(i)) {
+            match w.path {
+                gravity::Path::Orbit { ref mut center, .. } => { center.add(&d); },
+                _ => { w.pos.add(&d); },
+            }
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = false;
+    }
+
+    pub fn remove(&mut self, wells: &mut Vec<Well>, time: f32, p: vec::Vec) {
+        if let Some(i) = WellEditor::pick(wells, time, p) {
+            wells.remove(i);
+            self.selected = None;
+        }
+    }
+
+    // The wheel tunes the hovered well, or the selected one; going below zero
+    // turns an attractor into a repeller
+    pub fn scroll(&mut self, wells: &mut [Well], time: f32, p: vec::Vec, amount: f32) {
+        let i = WellEditor::pick(wells, time, p).or(self.selected);
+        if let Some(w) = i.and_then(|i| wells.get_mut(i)) {
+            w.strength += amount * WHEEL_STEP;
+            self.selected = i;
+        }
+    }
+
+    pub fn draw(&self, wells: &[Well], time: f32, mouse: vec::Vec, mb: &mut graphics::MeshBuilder) {
+        for (i, w) in wells.iter().enumerate() {
+            let at = w.at(time, mouse);
+            let p = ggez::nalgebra::Point2::new(at.pos.x, at.pos.y);
+            let col = if w.strength >= 0.0 {
+                graphics::Color::new(1.0, 0.6, 0.1, 0.8)
+            } else {
+                graphics::Color::new(0.2, 0.8, 1.0, 0.8)
+            };
+            mb.circle(graphics::DrawMode::fill(), p, 4.0, 0.5, col);
+            if !self.enabled { continue; }
+            let width = if self.selected == Some(i) { 3.0 } else { 1.0 };
+            mb.circle(graphics::DrawMode::stroke(width), p, ring_radius(w), 0.5, col);
+        }
+    }
+}
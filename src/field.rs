@@ -0,0 +1,227 @@
+use serde::{Serialize, Deserialize};
+use crate::vec;
+
+// Forces evaluated per particle on top of the gravity wells. Every group can
+// be switched on and off without losing its parameters.
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UniformGravity {
+    pub enabled: bool,
+    pub g: f32,
+    // Direction in degrees, 90 points down the screen
+    pub angle: f32,
+}
+
+// Inside `rect` (x, y, w, h) the velocity is pulled towards `vel`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WindZone {
+    pub rect: [f32; 4],
+    pub coupling: f32,
+    pub vel: vec::Vec,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Wind {
+    pub enabled: bool,
+    pub zones: Vec<WindZone>,
+}
+
+// Tangential pull strength * r / (r^2 + radius^2), counter clockwise when
+// positive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vortex {
+    pub strength: f32,
+    pub radius: f32,
+    pub pos: vec::Vec,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vortices {
+    pub enabled: bool,
+    pub list: Vec<Vortex>,
+}
+
+// Divergence free turbulence from the curl of a scrolling noise potential
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CurlNoise {
+    pub enabled: bool,
+    pub strength: f32,
+    // Size in pixels of the noise features
+    pub scale: f32,
+    // How fast the pattern evolves per unit of time
+    pub speed: f32,
+    pub seed: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Boundary {
+    Wrap,
+    // Particles bounce back keeping `restitution` of their normal speed
+    Walls { restitution: f32 },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fields {
+    pub boundary: Boundary,
+    pub gravity: UniformGravity,
+    pub noise: CurlNoise,
+    pub wind: Wind,
+    pub vortices: Vortices,
+}
+
+impl Fields {
+    // Everything off, with parameters that make sense for a w x h world
+    pub fn new(w: f32, h: f32) -> Fields {
+        Fields {
+            boundary: Boundary::Wrap,
+            gravity: UniformGravity { enabled: false, g: 0.03, angle: 90.0 },
+            noise: CurlNoise { enabled: false, strength: 0.05, scale: 150.0, speed: 0.005, seed: 1 },
+            wind: Wind {
+                enabled: false,
+                zones: vec![
+                    WindZone { rect: [0.0, 0.0, w, h / 3.0], coupling: 0.02, vel: vec::Vec::new_from(2.0, 0.0) },
+                    WindZone { rect: [0.0, h * 2.0 / 3.0, w, h / 3.0], coupling: 0.02, vel: vec::Vec::new_from(-2.0, 0.0) },
+                ],
+            },
+            vortices: Vortices {
+                enabled: false,
+                list: vec![
+                    Vortex { strength: 1.0, radius: w.min(h) * 0.15, pos: vec::Vec::new_from(w * 0.5, h * 0.5) },
+                ],
+            },
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Boundary::Walls { restitution } = self.boundary {
+            if !(0.0..=1.0).contains(&restitution) {
+                return Err(format!("restitution {} is outside [0, 1]", restitution));
+            }
+        }
+        if self.noise.scale <= 0.0 {
+            return Err("noise scale must be positive".to_string());
+        }
+        if self.vortices.list.iter().any(|v| v.radius <= 0.0) {
+            return Err("vortex radius must be positive".to_string());
+        }
+        if self.wind.zones.iter().any(|z| z.rect[2] < 0.0 || z.rect[3] < 0.0) {
+            return Err("wind zone size can't be negative".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn accel(&self, pos: &vec::Vec, vel: &vec::Vec, time: f32) -> vec::Vec {
+        let mut acc = vec::Vec::new();
+
+        if self.gravity.enabled {
+            let a = self.gravity.angle.to_radians();
+            acc.add(&vec::Vec::new_from(a.cos() * self.gravity.g, a.sin() * self.gravity.g));
+        }
+
+        if self.wind.enabled {
+            for z in self.wind.zones.iter() {
+                let [x, y, w, h] = z.rect;
+                if pos.x >= x && pos.x < x + w && pos.y >= y && pos.y < y + h {
+                    let mut d = z.vel;
+                    acc.add(d.sub(vel).mul(z.coupling));
+                }
+            }
+        }
+
+        if self.vortices.enabled {
+            for v in self.vortices.list.iter() {
+                let dx = pos.x - v.pos.x;
+                let dy = pos.y - v.pos.y;
+                let k = v.strength / (dx * dx + dy * dy + v.radius * v.radius);
+                acc.add(&vec::Vec::new_from(-dy * k, dx * k));
+            }
+        }
+
+        if self.noise.enabled {
+            acc.add(&self.noise.curl(pos, time));
+        }
+        acc
+    }
+
+    // Only the uniform gravity is conservative
+    pub fn potential(&self, pos: &vec::Vec) -> f32 {
+        if !self.gravity.enabled {
+            return 0.0;
+        }
+        let a = self.gravity.angle.to_radians();
+        -(a.cos() * pos.x + a.sin() * pos.y) * self.gravity.g
+    }
+
+    // Distances wrap around the world only when the boundary does
+    pub fn period(&self, w: f32, h: f32) -> (f32, f32) {
+        match self.boundary {
+            Boundary::Wrap => (w, h),
+            Boundary::Walls { .. } => (f32::INFINITY, f32::INFINITY),
+        }
+    }
+
+    pub fn apply_boundary(&self, pos: &mut vec::Vec, vel: &mut vec::Vec, w: f32, h: f32) {
+        match self.boundary {
+            Boundary::Wrap => {
+                while pos.x > w { pos.x-= w }
+                while pos.x < 0.0 { pos.x+= w }
+                while pos.y > h { pos.y-= h }
+                while pos.y < 0.0 { pos.y+= h }
+            }
+            Boundary::Walls { restitution } => {
+                if pos.x < 0.0 { pos.x = -pos.x; vel.x = vel.x.abs() * restitution; }
+                if pos.x > w { pos.x = 2.0 * w - pos.x; vel.x = -vel.x.abs() * restitution; }
+                if pos.y < 0.0 { pos.y = -pos.y; vel.y = vel.y.abs() * restitution; }
+                if pos.y > h { pos.y = 2.0 * h - pos.y; vel.y = -vel.y.abs() * restitution; }
+                pos.x = pos.x.clamp(0.0, w);
+                pos.y = pos.y.clamp(0.0, h);
+            }
+        }
+    }
+}
+
+impl CurlNoise {
+    fn potential(&self, x: f32, y: f32, t: f32) -> f32 {
+        value_noise(x / self.scale, y / self.scale, t * self.speed, self.seed)
+    }
+
+    pub fn curl(&self, pos: &vec::Vec, time: f32) -> vec::Vec {
+        let e = self.scale * 0.05;
+        let dpdx = self.potential(pos.x + e, pos.y, time) - self.potential(pos.x - e, pos.y, time);
+        let dpdy = self.potential(pos.x, pos.y + e, time) - self.potential(pos.x, pos.y - e, time);
+        // Normalised so `strength` is roughly the peak acceleration
+        let k = self.strength * self.scale / (2.0 * e);
+        vec::Vec::new_from(dpdy * k, -dpdx * k)
+    }
+}
+
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> f32 {
+    let mut h = seed
+        .wrapping_add((x as u32).wrapping_mul(374_761_393))
+        .wrapping_add((y as u32).wrapping_mul(668_265_263))
+        .wrapping_add((z as u32).wrapping_mul(2_147_483_647));
+    h = (h ^ (h >> 13)).wrapping_mul(1_274_126_177);
+    h ^= h >> 16;
+    h as f32 / u32::MAX as f32
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Smoothly interpolated lattice noise in [0, 1]
+fn value_noise(x: f32, y: f32, z: f32, seed: u32) -> f32 {
+    let (xi, yi, zi) = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+    let (tx, ty, tz) = (smooth(x - xi as f32), smooth(y - yi as f32), smooth(z - zi as f32));
+    let layer = |z: i32| {
+        let a = lerp(hash(xi, yi, z, seed), hash(xi + 1, yi, z, seed), tx);
+        let b = lerp(hash(xi, yi + 1, z, seed), hash(xi + 1, yi + 1, z, seed), tx);
+        lerp(a, b, ty)
+    };
+    lerp(layer(zi), layer(zi + 1), tz)
+}
@@ -0,0 +1,238 @@
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::nalgebra::{Point2, Vector2};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::ag::Agent;
+use crate::latex::Latex2D;
+use crate::sim::Sim;
+use crate::vec;
+
+// Pixels per LIC texel
+const LIC_TEXEL: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overlay {
+    None,
+    Arrows,
+    Streamlines,
+    // Line integral convolution, noise smeared along the flow
+    Lic,
+}
+
+impl Overlay {
+    pub fn next(self) -> Overlay {
+        match self {
+            Overlay::None => Overlay::Arrows,
+            Overlay::Arrows => Overlay::Streamlines,
+            Overlay::Streamlines => Overlay::Lic,
+            Overlay::Lic => Overlay::None,
+        }
+    }
+}
+
+// Velocity of the agents resampled on a regular grid, wrapping around the
+// world edges like the agents do
+pub struct Grid {
+    pub nx: usize,
+    pub ny: usize,
+    pub cell: f32,
+    pub vel: Vec<vec::Vec>,
+    pub max_speed: f32,
+}
+
+impl Grid {
+    // Each node averages the agents within 1.5 cells, closer ones weigh more
+    pub fn resample(sim: &Sim, latex: &Latex2D<Agent>, cell: f32) -> Grid {
+        let nx = ((sim.w / cell).round() as usize).max(1);
+        let ny = ((sim.h / cell).round() as usize).max(1);
+        let cell = sim.w / nx as f32;
+        let r = cell * 1.5;
+        let (pw, ph) = sim.fields.period(sim.w, sim.h);
+        let vel = (0..nx * ny).into_par_iter().map(|i| {
+            let p = vec::Vec::new_from((i % nx) as f32 * cell + cell / 2.0, (i / nx) as f32 * cell + cell / 2.0);
+            let mut v = vec::Vec::new();
+            let mut total = 0.0;
+            for a in latex.get((p.x, p.y), r).iter() {
+                let d = p.dist_mod(&a.pos, pw, ph);
+                if d >= r { continue; }
+                let k = (1.0 - (d / r).powi(2)).powi(2);
+                v.add(&vec::Vec::new_from(a.vel.x * k, a.vel.y * k));
+                total += k;
+            }
+            if total > 0.0 { v.div(total); }
+            v
+        }).collect::<Vec<vec::Vec>>();
+        let max_speed = vel.iter().map(|v| v.mag()).fold(0.0, f32::max);
+        Grid { nx, ny, cell, vel, max_speed }
+    }
+
+    fn node(&self, x: i64, y: i64) -> vec::Vec {
+        let x = x.rem_euclid(self.nx as i64) as usize;
+        let y = y.rem_euclid(self.ny as i64) as usize;
+        self.vel[y * self.nx + x]
+    }
+
+    // Bilinear interpolation between the nodes around `(x, y)`
+    pub fn at(&self, x: f32, y: f32) -> vec::Vec {
+        let gx = x / self.cell - 0.5;
+        let gy = y / self.cell - 0.5;
+        let (x0, y0) = (gx.floor(), gy.floor());
+        let (fx, fy) = (gx - x0, gy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let mix = |a: vec::Vec, b: vec::Vec, f: f32| vec::Vec::new_from(a.x + (b.x - a.x) * f, a.y + (b.y - a.y) * f);
+        let top = mix(self.node(x0, y0), self.node(x0 + 1, y0), fx);
+        let bottom = mix(self.node(x0, y0 + 1), self.node(x0 + 1, y0 + 1), fx);
+        mix(top, bottom, fy)
+    }
+
+    // Points along the flow from `(x, y)`, `steps` of `h` pixels, midpoint
+    // method. A negative `h` goes upstream. Stops where the flow does.
+    pub fn trace(&self, x: f32, y: f32, h: f32, steps: usize) -> Vec<(f32, f32)> {
+        let max = self.max_speed;
+        let mut p = (x, y);
+        let mut line = vec![p];
+        if max <= 0.0 { return line; }
+        let dir = |p: (f32, f32)| {
+            let v = self.at(p.0, p.1);
+            let m = v.mag();
+            if m < max * 0.01 { None } else { Some((v.x / m, v.y / m)) }
+        };
+        for _ in 0..steps {
+            let d = match dir(p) { Some(d) => d, None => break };
+            let mid = (p.0 + d.0 * h / 2.0, p.1 + d.1 * h / 2.0);
+            let d = match dir(mid) { Some(d) => d, None => break };
+            p = (p.0 + d.0 * h, p.1 + d.1 * h);
+            line.push(p);
+        }
+        line
+    }
+}
+
+// Segments of an arrow per node, the fastest as long as `length` cells
+pub fn arrows(grid: &Grid, length: f32) -> Vec<((f32, f32), (f32, f32))> {
+    let max = grid.max_speed;
+    if max <= 0.0 { return vec![]; }
+    let k = length * grid.cell / max;
+    let mut segments = vec![];
+    for (i, v) in grid.vel.iter().enumerate() {
+        if v.mag() < max * 0.02 { continue; }
+        let a = ((i % grid.nx) as f32 * grid.cell + grid.cell / 2.0, (i / grid.nx) as f32 * grid.cell + grid.cell / 2.0);
+        let b = (a.0 + v.x * k, a.1 + v.y * k);
+        // Head, two strokes at 30 degrees from the shaft
+        let (dx, dy) = ((a.0 - b.0) * 0.3, (a.1 - b.1) * 0.3);
+        let (c, s) = (0.866, 0.5);
+        segments.push((a, b));
+        segments.push((b, (b.0 + dx * c - dy * s, b.1 + dx * s + dy * c)));
+        segments.push((b, (b.0 + dx * c + dy * s, b.1 - dx * s + dy * c)));
+    }
+    segments
+}
+
+// A line through every `spacing` cells, `length` cells up and downstream
+pub fn streamlines(grid: &Grid, spacing: usize, length: f32) -> Vec<Vec<(f32, f32)>> {
+    let h = grid.cell / 2.0;
+    let steps = (length * 2.0).ceil() as usize;
+    let mut lines = vec![];
+    for y in (0..grid.ny).step_by(spacing.max(1)) {
+        for x in (0..grid.nx).step_by(spacing.max(1)) {
+            let (px, py) = ((x as f32 + 0.5) * grid.cell, (y as f32 + 0.5) * grid.cell);
+            let mut line = grid.trace(px, py, -h, steps);
+            line.reverse();
+            line.extend(grid.trace(px, py, h, steps).into_iter().skip(1));
+            if line.len() > 2 { lines.push(line); }
+        }
+    }
+    lines
+}
+
+// Same noise for the same texel every frame, without touching the RNG the
+// simulation is seeded with
+fn noise(x: usize, y: usize) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+// Grey levels of a `w` x `h` texture of `texel` pixels, noise averaged along
+// `length` cells of flow through each texel
+pub fn lic(grid: &Grid, w: usize, h: usize, texel: f32, length: f32) -> Vec<f32> {
+    let steps = (length * grid.cell / texel).ceil() as usize;
+    (0..w * h).into_par_iter().map(|i| {
+        let (x, y) = ((i % w) as f32 * texel + texel / 2.0, (i / w) as f32 * texel + texel / 2.0);
+        let mut sum = 0.0;
+        let mut n = 0;
+        for dir in [-texel, texel].iter() {
+            for p in grid.trace(x, y, *dir, steps).iter().skip(if *dir > 0.0 { 1 } else { 0 }) {
+                let tx = ((p.0 / texel).floor() as i64).rem_euclid(w as i64) as usize;
+                let ty = ((p.1 / texel).floor() as i64).rem_euclid(h as i64) as usize;
+                sum += noise(tx, ty);
+                n += 1;
+            }
+        }
+        sum / n as f32
+    }).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct Flow {
+    pub overlay: Overlay,
+    // Grid spacing in pixels, smaller is denser
+    pub cell: f32,
+    // Arrow and line length, in cells
+    pub length: f32,
+}
+
+impl Default for Flow {
+    fn default() -> Flow {
+        Flow { overlay: Overlay::None, cell: 20.0, length: 1.0 }
+    }
+}
+
+impl Flow {
+    pub fn draw(&self, ctx: &mut Context, sim: &Sim, latex: &Latex2D<Agent>) -> GameResult<()> {
+        if self.overlay == Overlay::None { return Ok(()); }
+        let grid = Grid::resample(sim, latex, self.cell);
+        let p = |(x, y): (f32, f32)| Point2::new(x, y);
+        let mut mb = graphics::MeshBuilder::new();
+        match self.overlay {
+            Overlay::None => {},
+            Overlay::Arrows => {
+                let col = graphics::Color::new(1.0, 1.0, 1.0, 0.7);
+                for (a, b) in arrows(&grid, self.length).into_iter() {
+                    if (a.0 - b.0).abs() + (a.1 - b.1).abs() < 0.1 { continue; }
+                    mb.line(&[p(a), p(b)], 1.0, col)?;
+                }
+            },
+            Overlay::Streamlines => {
+                let col = graphics::Color::new(0.6, 0.85, 1.0, 0.6);
+                for line in streamlines(&grid, 2, self.length * 4.0).into_iter() {
+                    // Lines crossing the world edge are cut there
+                    let wrapped: Vec<Point2<f32>> = line.iter().map(|q| p((q.0.rem_euclid(sim.w), q.1.rem_euclid(sim.h)))).collect();
+                    for part in wrapped.chunk_by(|a, b| (a.x - b.x).abs() < grid.cell && (a.y - b.y).abs() < grid.cell) {
+                        if part.len() > 1 { mb.line(part, 1.0, col)?; }
+                    }
+                }
+            },
+            Overlay::Lic => {
+                let (w, h) = ((sim.w / LIC_TEXEL) as usize, (sim.h / LIC_TEXEL) as usize);
+                let grey = lic(&grid, w, h, LIC_TEXEL, self.length * 4.0);
+                // Stretch the contrast, averaging flattens it around 0.5
+                let rgba: Vec<u8> = grey.iter().flat_map(|g| {
+                    let v = (((g - 0.5) * 3.0 + 0.5).clamp(0.0, 1.0) * 255.0) as u8;
+                    vec![v, v, v, 150]
+                }).collect();
+                let img = graphics::Image::from_rgba8(ctx, w as u16, h as u16, &rgba)?;
+                graphics::draw(ctx, &img, graphics::DrawParam::new().scale(Vector2::new(LIC_TEXEL, LIC_TEXEL)))?;
+                return Ok(());
+            },
+        }
+        if let Ok(mesh) = mb.build(ctx) {
+            graphics::draw(ctx, &mesh, graphics::DrawParam::new())?;
+        }
+        Ok(())
+    }
+}
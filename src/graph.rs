@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::nalgebra::Point2;
+use crate::metrics::{self, Metrics};
+use crate::sim::Sim;
+
+// Steps between two samples, and samples kept
+const EVERY: u64 = 10;
+const LEN: usize = 600;
+
+const MARGIN: f32 = 10.0;
+const WIDTH: f32 = 360.0;
+const HEIGHT: f32 = 70.0;
+// Space for the title above each plot
+const TITLE: f32 = 16.0;
+
+type Line = (&'static str, [f32; 3], fn(&Metrics) -> f32);
+
+// Name used in the config file, and the lines of the plot
+const PLOTS: &[(&str, &[Line])] = &[
+    ("speed", &[
+        ("mean", [0.3, 0.7, 1.0], |m| m.mean_speed),
+        ("max", [1.0, 1.0, 1.0], |m| m.max_speed),
+    ]),
+    ("density", &[
+        ("mean neighbours", [1.0, 0.8, 0.2], |m| m.mean_neighbours),
+    ]),
+    ("colors", &[
+        ("red", [1.0, 0.2, 0.2], |m| m.color_count[0] as f32),
+        ("green", [0.2, 1.0, 0.2], |m| m.color_count[1] as f32),
+        ("blue", [0.3, 0.4, 1.0], |m| m.color_count[2] as f32),
+    ]),
+    ("energy", &[
+        ("kinetic", [1.0, 0.5, 0.1], |m| m.kinetic_energy),
+    ]),
+    ("momentum", &[
+        ("|p|", [0.8, 0.5, 1.0], |m| (m.momentum[0].powi(2) + m.momentum[1].powi(2)).sqrt()),
+    ]),
+];
+
+pub fn check(names: &[String]) -> Result<(), String> {
+    match names.iter().find(|n| !PLOTS.iter().any(|(p, _)| p == n)) {
+        Some(n) => Err(format!("unknown graph '{}', use one of {}", n,
+            PLOTS.iter().map(|(p, _)| *p).collect::<Vec<_>>().join(", "))),
+        None => Ok(()),
+    }
+}
+
+// Plots of the metrics over the last LEN samples in the bottom left corner,
+// each scaled to fit what it shows
+pub struct Graph {
+    pub enabled: bool,
+    pub shown: Vec<String>,
+    samples: VecDeque<Metrics>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            enabled: false,
+            shown: ["speed", "density", "colors", "energy"].iter().map(|s| s.to_string()).collect(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    // Call after every step. Samples are taken while hidden too, so the
+    // history is there when the graph is opened.
+    pub fn step(&mut self, sim: &Sim) {
+        // Undo and scrubbing go back in time, what came after is gone
+        while self.samples.back().is_some_and(|m| m.step >= sim.clock.steps) {
+            self.samples.pop_back();
+        }
+        if !sim.clock.steps.is_multiple_of(EVERY) { return; }
+        self.samples.push_back(metrics::measure(sim));
+        if self.samples.len() > LEN { self.samples.pop_front(); }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        if !self.enabled || self.len() < 2 { return Ok(()); }
+        let (_, h) = graphics::drawable_size(ctx);
+        let plots: Vec<&(&str, &[Line])> = PLOTS.iter().filter(|(p, _)| self.shown.iter().any(|s| s == p)).collect();
+        let span = self.samples.back().unwrap().step - self.samples.front().unwrap().step;
+        let dx = WIDTH / (LEN - 1) as f32;
+
+        let mut mb = graphics::MeshBuilder::new();
+        let mut texts = vec![];
+        let top = h - MARGIN - plots.len() as f32 * (HEIGHT + TITLE);
+        mb.rectangle(graphics::DrawMode::fill(),
+            graphics::Rect::new(MARGIN - 5.0, top - 5.0, WIDTH + 10.0, h - MARGIN - top + 10.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.6));
+        for (i, (name, lines)) in plots.iter().enumerate() {
+            let y0 = top + i as f32 * (HEIGHT + TITLE) + TITLE;
+            let values: Vec<f32> = self.samples.iter().flat_map(|m| lines.iter().map(move |l| (l.2)(m))).collect();
+            let mut min = values.iter().cloned().fold(f32::MAX, f32::min);
+            let mut max = values.iter().cloned().fold(f32::MIN, f32::max);
+            if max - min < 1e-6 {
+                min -= 0.5;
+                max += 0.5;
+            }
+            mb.rectangle(graphics::DrawMode::stroke(1.0),
+                graphics::Rect::new(MARGIN, y0, WIDTH, HEIGHT),
+                graphics::Color::new(0.5, 0.5, 0.5, 0.8));
+
+            let mut title = name.to_string();
+            for (label, col, get) in lines.iter() {
+                let x0 = MARGIN + WIDTH - (self.samples.len() - 1) as f32 * dx;
+                let points: Vec<Point2<f32>> = self.samples.iter().enumerate()
+                    .map(|(j, m)| Point2::new(x0 + j as f32 * dx, y0 + HEIGHT * (1.0 - (get(m) - min) / (max - min))))
+                    .collect();
+                mb.line(&points, 1.0, graphics::Color::new(col[0], col[1], col[2], 1.0))?;
+                title.push_str(&format!("  {} {:.3}", label, get(self.samples.back().unwrap())));
+            }
+            texts.push((title, MARGIN, y0 - TITLE + 2.0));
+            texts.push((format!("{:.3}", max), MARGIN + 3.0, y0 + 2.0));
+            texts.push((format!("{:.3}", min), MARGIN + 3.0, y0 + HEIGHT - 14.0));
+        }
+        texts.push((format!("last {} steps", span), MARGIN + WIDTH - 110.0, h - MARGIN - 14.0));
+
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::new())?;
+        for (text, x, y) in texts {
+            graphics::draw(ctx, &graphics::Text::new(text), graphics::DrawParam::new().dest(Point2::new(x, y)))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use serde::{Serialize, Deserialize};
+use crate::vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Path {
+    #[default]
+    Fixed,
+    // Goes round an ellipse around `center` with half axes `radius`, the angle
+    // grows by `speed` radians per unit of time
+    Orbit { speed: f32, radius: vec::Vec, center: vec::Vec },
+    Mouse,
+}
+
+// Plain values come before `pos` and `path`, TOML can't emit them after tables
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Well {
+    // Positive attracts, negative repels
+    pub strength: f32,
+    // Added in quadrature to the distance so the force stays finite at the
+    // centre
+    pub softening: f32,
+    // The pull decays as (softening^2 + d^2)^(falloff/2)
+    pub falloff: f32,
+    pub pos: vec::Vec,
+    #[serde(default)]
+    pub path: Path,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WellFile {
+    #[serde(default)]
+    wells: Vec<Well>,
+}
+
+// Strength of a well created with a given `gravity_f`, as the old switch did
+pub fn strength(gravity_f: f32) -> f32 {
+    gravity_f.powf(1.4) * 0.2
+}
+
+impl Well {
+    pub fn new(pos: vec::Vec, strength: f32) -> Well {
+        Well {
+            strength,
+            softening: 10.0,
+            falloff: 2.0,
+            pos,
+            path: Path::Fixed,
+        }
+    }
+
+    pub fn with_path(mut self, path: Path) -> Well {
+        self.path = path;
+        self
+    }
+
+    // Where the well is at simulated `time`
+    pub fn at(&self, time: f32, mouse: vec::Vec) -> Well {
+        let pos = match self.path {
+            Path::Fixed => self.pos,
+            Path::Orbit { center, radius, speed } => vec::Vec::new_from(
+                center.x + (time * speed).cos() * radius.x,
+                center.y + (time * speed).sin() * radius.y,
+            ),
+            Path::Mouse => mouse,
+        };
+        Well { pos, path: Path::Fixed, ..*self }
+    }
+
+    fn soft_dist2(&self, pos: &vec::Vec) -> f32 {
+        let d = self.pos.dist(pos);
+        self.softening * self.softening + d * d
+    }
+
+    pub fn accel(&self, pos: &vec::Vec) -> vec::Vec {
+        let mut g = self.pos;
+        g.sub(pos);
+        g.mul(self.strength / self.soft_dist2(pos).powf(self.falloff / 2.0));
+        g
+    }
+
+    // Potential energy per unit of mass, consistent with `accel`
+    pub fn potential(&self, pos: &vec::Vec) -> f32 {
+        let r2 = self.soft_dist2(pos);
+        if (self.falloff - 2.0).abs() < 1e-6 {
+            self.strength * 0.5 * r2.ln()
+        } else {
+            self.strength / (2.0 - self.falloff) * r2.powf(1.0 - self.falloff / 2.0)
+        }
+    }
+}
+
+// The canned layouts of the old `gravity_mod` switch
+pub fn preset(n: usize, w: f32, h: f32, strength: f32) -> Vec<Well> {
+    let center = vec::Vec::new_from(w*0.5, h*0.5);
+    match n {
+        1 => vec![
+            Well::new(center, strength),
+        ],
+        2 => vec![
+            Well::new(center, strength),
+            Well::new(center, strength).with_path(Path::Orbit { center, radius: vec::Vec::new_from(w*0.3, h*0.3), speed: 0.03 }),
+        ],
+        3 => vec![
+            Well::new(center, strength),
+            Well::new(center, strength).with_path(Path::Mouse),
+        ],
+        4 => vec![
+            Well::new(center, strength).with_path(Path::Mouse),
+        ],
+        _ => vec![],
+    }
+}
+
+pub fn validate(wells: &[Well]) -> Result<(), String> {
+    for w in wells.iter() {
+        if !w.softening.is_finite() || w.softening <= 0.0 || !w.strength.is_finite() || !w.falloff.is_finite() {
+            return Err(format!("invalid well at ({}, {})", w.pos.x, w.pos.y));
+        }
+    }
+    Ok(())
+}
+
+pub fn save(path: &str, wells: &[Well]) -> Result<(), String> {
+    let file = WellFile { wells: wells.to_vec() };
+    let text = toml::to_string_pretty(&file).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+pub fn load(path: &str) -> Result<Vec<Well>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: WellFile = toml::from_str(&text).map_err(|e| e.to_string())?;
+    Ok(file.wells)
+}
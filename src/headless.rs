@@ -0,0 +1,129 @@
+use serde_json::json;
+use crate::cli;
+use crate::config;
+use crate::dump;
+use crate::metrics;
+use crate::gravity;
+use crate::npy;
+use crate::output::{self, Sink};
+use crate::raster;
+use crate::sim::Sim;
+use crate::surface;
+use crate::svg;
+use crate::term;
+use crate::utils;
+use crate::vec;
+
+// Runs the simulation without a window, as fast as it goes, handing a
+// rendered frame to every output each `args.every` steps.
+pub fn run(args: &cli::Args, seed: u64, mut sim: Sim) -> Result<(), String> {
+    if args.y4m.as_deref() == Some("-") || args.term.is_some() {
+        utils::log_to_stderr();
+    }
+    let (w, h) = args.resolution.unwrap_or((sim.w, sim.h));
+    let mut renderer = raster::Renderer::new(w as usize, h as usize);
+    configure(&mut sim, &mut renderer, config::CONFIG_FILE)?;
+    let mut metrics = match &args.metrics {
+        Some(path) => Some(metrics::Stream::create(path, args.metrics_every)?),
+        None => None,
+    };
+
+    let mut sinks: Vec<Box<dyn Sink>> = vec![];
+    if let Some(dir) = &args.png {
+        sinks.push(Box::new(output::PngSequence::new(dir)?));
+    }
+    if let Some(dir) = &args.svg {
+        sinks.push(Box::new(output::SvgSequence::new(dir, svg::Options { arrows: args.svg_arrows })?));
+    }
+    if let Some(path) = &args.y4m {
+        sinks.push(Box::new(output::Y4m::create(path, (sim.clock.rate as u32, args.every as u32))?));
+    }
+    if let Some(dir) = &args.vtk {
+        sinks.push(Box::new(dump::Dump::new(dir, dump::Format::Vtk)?));
+    }
+    if let Some(dir) = &args.csv {
+        sinks.push(Box::new(dump::Dump::new(dir, dump::Format::Csv)?));
+    }
+    if let Some(dir) = &args.npy {
+        sinks.push(Box::new(npy::Trajectory::new(dir, seed)?));
+    }
+    if let Some(dir) = &args.contours {
+        sinks.push(Box::new(surface::Contours::new(dir, renderer.surface.clone())?));
+    }
+    if let Some(style) = args.term {
+        sinks.push(Box::new(term::Terminal::new(style, args.term_size.unwrap_or_else(term::size), args.fps)));
+    }
+
+    sim.latex_div = args.latex_div;
+    utils::log("headless", json!({
+        "seed": seed,
+        "w": sim.w,
+        "h": sim.h,
+        "agents": sim.agents.len(),
+        "steps": args.steps,
+        "every": args.every,
+        "latex_div": sim.latex_div,
+    }));
+
+    let t_start = utils::now();
+    let canvas = sinks.iter().any(|s| s.needs_canvas());
+    let mut frames = 0;
+    // 0 steps runs until interrupted
+    while args.steps == 0 || sim.clock.steps < args.steps {
+        sim.step(vec::Vec::new());
+        sim.clock.tick();
+        if let Some(m) = metrics.as_mut() {
+            m.step(&sim);
+        }
+        if sinks.is_empty() || !sim.clock.steps.is_multiple_of(args.every) {
+            continue;
+        }
+        if canvas {
+            renderer.draw(&sim);
+        } else {
+            renderer.track(&sim);
+        }
+        for s in sinks.iter_mut() {
+            s.frame(&sim, &renderer)?;
+        }
+        frames += 1;
+    }
+    for s in sinks.iter_mut() {
+        s.finish()?;
+    }
+    utils::log("headless_done", json!({
+        "steps": sim.clock.steps,
+        "frames": frames,
+        "time": utils::now() - t_start,
+    }));
+    Ok(())
+}
+
+// The [params], [fields] and [wells] sections of the config file, if there
+// is one, and the colour and surface keys of [render]. Unlike in the game, a
+// bad file stops the run.
+fn configure(sim: &mut Sim, renderer: &mut raster::Renderer, path: &str) -> Result<(), String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(_) => return Ok(()),
+    };
+    let table = toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let cfg = config::Config::parse(&table).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(p) = cfg.params.as_ref() {
+        sim.params = config::merge(&sim.params, p)?;
+        sim.params.validate()?;
+    }
+    if let Some(f) = cfg.fields.as_ref() {
+        sim.fields = config::merge(&sim.fields, f)?;
+        sim.fields.validate()?;
+    }
+    if let Some(r) = cfg.render.as_ref() {
+        renderer.coloring = r.coloring(&renderer.coloring)?;
+        renderer.surface = r.surface(&renderer.surface)?;
+    }
+    if let Some(w) = cfg.wells {
+        gravity::validate(&w)?;
+        sim.wells = w;
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use crate::ag::Agent;
+use crate::gravity::Well;
+use crate::vec;
+
+// What is needed to rebuild an agent, the rest is derived on the next step
+#[derive(Clone, Copy)]
+struct Compact {
+    pos: [f32; 2],
+    vel: [f32; 2],
+    color: [f32; 3],
+    frozen: bool,
+    class: usize,
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub step: u64,
+    pub time: f64,
+    pub wells: Vec<Well>,
+    agents: Vec<Compact>,
+    // Taken right before an edit rather than by the timer
+    edit: bool,
+}
+
+impl Snapshot {
+    pub fn new(step: u64, time: f64, agents: &[Agent], wells: &[Well]) -> Snapshot {
+        Snapshot {
+            step,
+            time,
+            wells: wells.to_vec(),
+            agents: agents.iter().map(|x| Compact {
+                pos: [x.pos.x, x.pos.y],
+                vel: [x.vel.x, x.vel.y],
+                color: x.color,
+                frozen: x.frozen,
+                class: x.class,
+            }).collect(),
+            edit: false,
+        }
+    }
+
+    pub fn agents(&self) -> Vec<Agent> {
+        self.agents.iter().enumerate().map(|(i, c)| {
+            let mut x = Agent::new(i, vec::Vec::new_from(c.pos[0], c.pos[1]), vec::Vec::new_from(c.vel[0], c.vel[1]));
+            x.color = c.color;
+            x.frozen = c.frozen;
+            x.class = c.class;
+            x
+        }).collect()
+    }
+}
+
+// Bounded timeline of snapshots, taken every `every` steps and before edits.
+// While scrubbing `cursor` points at the snapshot on screen, stepping from
+// there branches: the snapshots after it are dropped.
+pub struct History {
+    pub every: u64,
+    capacity: usize,
+    snaps: VecDeque<Snapshot>,
+    pub cursor: Option<usize>,
+}
+
+impl History {
+    pub fn new(every: u64, capacity: usize) -> History {
+        History {
+            every,
+            capacity,
+            snaps: VecDeque::new(),
+            cursor: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snaps.len()
+    }
+
+    pub fn push(&mut self, snap: Snapshot) {
+        self.branch();
+        if self.snaps.len() >= self.capacity {
+            self.snaps.pop_front();
+        }
+        self.snaps.push_back(snap);
+    }
+
+    pub fn checkpoint(&mut self, mut snap: Snapshot) {
+        snap.edit = true;
+        self.push(snap);
+    }
+
+    // Forget the future of the snapshot being shown
+    pub fn branch(&mut self) {
+        if let Some(i) = self.cursor.take() {
+            self.snaps.truncate(i + 1);
+        }
+    }
+
+    // State before the last edit, dropping the periodic snapshots taken
+    // since, or without edits the latest one older than `step`. It's removed
+    // so repeated undos keep going back.
+    pub fn undo(&mut self, step: u64) -> Option<Snapshot> {
+        self.branch();
+        if let Some(i) = self.snaps.iter().rposition(|s| s.edit) {
+            self.snaps.truncate(i + 1);
+            return self.snaps.pop_back();
+        }
+        while let Some(s) = self.snaps.pop_back() {
+            if s.step < step || self.snaps.is_empty() {
+                return Some(s);
+            }
+        }
+        None
+    }
+
+    // Move the cursor by `n` snapshots, starting from the latest one
+    pub fn scrub(&mut self, n: isize) -> Option<&Snapshot> {
+        if self.snaps.is_empty() { return None; }
+        let last = self.snaps.len() as isize - 1;
+        let from = self.cursor.map(|c| c as isize).unwrap_or(last);
+        let i = (from + n).clamp(0, last) as usize;
+        self.cursor = Some(i);
+        self.snaps.get(i)
+    }
+
+    pub fn oldest(&mut self) -> Option<&Snapshot> {
+        if self.snaps.is_empty() { return None; }
+        self.cursor = Some(0);
+        self.snaps.front()
+    }
+}
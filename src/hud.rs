@@ -0,0 +1,37 @@
+use ggez::{Context, GameResult};
+use ggez::graphics;
+
+pub const LEVELS: usize = 4;
+
+// In-window text overlay. Verbosity 0 hides it, every level adds sections:
+// 1 rates and counts, 2 simulation parameters, 3 timer breakdown.
+pub struct Hud {
+    pub verbosity: usize,
+}
+
+impl Hud {
+    pub fn new() -> Hud {
+        Hud { verbosity: 1 }
+    }
+
+    pub fn cycle(&mut self) {
+        self.verbosity = (self.verbosity + 1) % LEVELS;
+    }
+
+    // `sections[i]` is shown from verbosity i + 1
+    pub fn draw(&self, ctx: &mut Context, sections: &[Vec<String>]) -> GameResult<()> {
+        if self.verbosity == 0 {
+            return Ok(());
+        }
+        let lines: Vec<String> = sections.iter().take(self.verbosity).flatten().cloned().collect();
+        let text = graphics::Text::new(lines.join("\n"));
+        let (_, h) = graphics::drawable_size(ctx);
+        let (tw, th) = text.dimensions(ctx);
+        let y = h - th as f32 - 10.0;
+        let bg = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
+            graphics::Rect::new(5.0, y - 5.0, tw as f32 + 10.0, th as f32 + 10.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.6))?;
+        graphics::draw(ctx, &bg, graphics::DrawParam::new())?;
+        graphics::draw(ctx, &text, graphics::DrawParam::new().dest(ggez::nalgebra::Point2::new(10.0, y)))
+    }
+}
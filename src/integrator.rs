@@ -0,0 +1,114 @@
+use crate::vec::Vec;
+
+#[derive(Clone, Copy, Debug)]
+pub struct State {
+    pub pos: Vec,
+    pub vel: Vec,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    Euler,
+    Verlet,
+    Leapfrog,
+    Rk4,
+}
+
+impl Integrator {
+    pub fn next(self) -> Integrator {
+        match self {
+            Integrator::Euler => Integrator::Verlet,
+            Integrator::Verlet => Integrator::Leapfrog,
+            Integrator::Leapfrog => Integrator::Rk4,
+            Integrator::Rk4 => Integrator::Euler,
+        }
+    }
+
+    // Advance `s` by `dt` given the acceleration for any (pos, vel) state
+    pub fn step<F>(self, s: State, dt: f32, acc: F) -> State
+    where F: Fn(&State) -> Vec {
+        match self {
+            // Semi-implicit: the new velocity moves the particle
+            Integrator::Euler => {
+                let a = acc(&s);
+                let vel = add(s.vel, a, dt);
+                State { pos: add(s.pos, vel, dt), vel }
+            }
+            // Velocity Verlet: a full drift with the current acceleration, then
+            // the average of the old and new one. The velocity fed to the second
+            // evaluation is predicted so drag and velocity matching still work.
+            Integrator::Verlet => {
+                let a0 = acc(&s);
+                let pos = add(add(s.pos, s.vel, dt), a0, 0.5 * dt * dt);
+                let a1 = acc(&State { pos, vel: add(s.vel, a0, dt) });
+                let mut a = a0;
+                a.add(&a1).mul(0.5);
+                State { pos, vel: add(s.vel, a, dt) }
+            }
+            // Drift-kick-drift
+            Integrator::Leapfrog => {
+                let half = add(s.pos, s.vel, 0.5 * dt);
+                let vel = add(s.vel, acc(&State { pos: half, vel: s.vel }), dt);
+                State { pos: add(half, vel, 0.5 * dt), vel }
+            }
+            Integrator::Rk4 => {
+                let k1v = acc(&s);
+                let k1x = s.vel;
+                let s2 = State { pos: add(s.pos, k1x, 0.5 * dt), vel: add(s.vel, k1v, 0.5 * dt) };
+                let k2v = acc(&s2);
+                let k2x = s2.vel;
+                let s3 = State { pos: add(s.pos, k2x, 0.5 * dt), vel: add(s.vel, k2v, 0.5 * dt) };
+                let k3v = acc(&s3);
+                let k3x = s3.vel;
+                let s4 = State { pos: add(s.pos, k3x, dt), vel: add(s.vel, k3v, dt) };
+                let k4v = acc(&s4);
+                let k4x = s4.vel;
+                State {
+                    pos: add(s.pos, weigh(k1x, k2x, k3x, k4x), dt / 6.0),
+                    vel: add(s.vel, weigh(k1v, k2v, k3v, k4v), dt / 6.0),
+                }
+            }
+        }
+    }
+}
+
+// a + b * k
+fn add(a: Vec, b: Vec, k: f32) -> Vec {
+    Vec::new_from(a.x + b.x * k, a.y + b.y * k)
+}
+
+fn weigh(k1: Vec, k2: Vec, k3: Vec, k4: Vec) -> Vec {
+    Vec::new_from(
+        k1.x + 2.0 * k2.x + 2.0 * k3.x + k4.x,
+        k1.y + 2.0 * k2.y + 2.0 * k3.y + k4.y,
+    )
+}
+
+// Tracks total energy against a reference taken when the experiment (or the
+// integrator) changed, so schemes can be compared on the same setup.
+#[derive(Default)]
+pub struct EnergyReport {
+    pub reference: Option<(u64, f64)>,
+}
+
+impl EnergyReport {
+    pub fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    // Returns the relative drift per 1000 steps since the reference
+    pub fn sample(&mut self, step: u64, energy: f64) -> Option<f64> {
+        match self.reference {
+            None => {
+                self.reference = Some((step, energy));
+                None
+            }
+            Some((s0, e0)) => {
+                if step <= s0 || e0 == 0.0 {
+                    return None;
+                }
+                Some((energy - e0) / e0.abs() / (step - s0) as f64 * 1000.0)
+            }
+        }
+    }
+}
@@ -2,31 +2,77 @@ use rayon::prelude::*;
 use ggez::{Context, ContextBuilder, GameResult};
 use ggez::event::{self, EventHandler};
 use ggez::graphics;
-use latex::Latex2D;
 use ggez::input::keyboard::KeyMods;
 use ggez::input::keyboard::KeyCode;
+use serde_json::json;
 
 mod vec;
 mod ag;
 mod utils;
 mod latex;
+mod clock;
+mod integrator;
+mod gravity;
+mod editor;
+mod field;
+mod brush;
+mod commands;
+mod config;
+mod hud;
+mod profiler;
+mod params;
+mod panel;
+mod history;
+mod replay;
+mod cli;
+mod sim;
+mod raster;
+mod output;
+mod headless;
+mod svg;
+mod term;
+mod dump;
+mod npy;
+mod metrics;
+mod graph;
+mod colormap;
+mod flow;
+mod surface;
 
 const AGENT_NUM: usize = 4000;
-const ST_LEN: usize = 40;
-const BRUSH_SIZE: f32 = 50.0;
-macro_rules! map(
-    { $($key:expr => $value:expr),+ } => {
-        {
-            let mut m = ::std::collections::HashMap::new();
-            $(
-                m.insert($key, $value);
-            )+
-            m
-        }
-     };
-);
-
+// The spawn brush stops there
+const AGENT_MAX: usize = AGENT_NUM * 2;
+// Simulation steps per second at `fast = 0`, each one advancing time by SIM_DT
+const SIM_RATE: f32 = 60.0;
+const SIM_DT: f32 = 1.0;
+// Snapshots are taken every HISTORY_EVERY steps and HISTORY_LEN are kept
+const HISTORY_EVERY: u64 = 30;
+const HISTORY_LEN: usize = 240;
+const WELLS_FILE: &str = "wells.toml";
+const TRACE_FILE: &str = "trace.json";
+const SVG_FILE: &str = "frame.svg";
+const CONTOURS_FILE: &str = "contours.json";
+const PRESET_FILE: &str = "preset.toml";
 fn main() {
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if args.headless {
+        let seed = args.seed.unwrap_or((utils::now() * 1e6) as u64);
+        utils::seed(seed);
+        let sim = sim::Sim::new(args.size.0, args.size.1, AGENT_NUM, clock::Clock::new(SIM_DT, SIM_RATE));
+        if let Err(e) = headless::run(&args, seed, sim) {
+            utils::log("error", json!({ "message": e }));
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // rayon::ThreadPoolBuilder::new().num_threads(12).build_global().expect("no thread pool");
 
     // Make a Context and an EventLoop.
@@ -57,75 +103,121 @@ fn main() {
     // Create an instance of your event handler.
     // Usually, you should provide it with the Context object
     // so it can load resources like images during setup.
-    let mut my_game = MyGame::new(&mut ctx);
+    let mut my_game = MyGame::new(&mut ctx, &args);
 
     // Run!
     match event::run(&mut ctx, &mut event_loop, &mut my_game) {
-        Ok(_) => println!("Exited cleanly."),
-        Err(e) => println!("Error occured: {}", e)
+        Ok(_) => utils::log("exit", json!({})),
+        Err(e) => utils::log("error", json!({ "message": e.to_string() }))
     }
 }
 
 struct MyGame {
     // Your state here...
-    agents: Vec<ag::Agent>,
+    sim: sim::Sim,
     frames: u32,
     frames_start: f64,
-    latex: Latex2D<ag::Agent>,
     // pool: scoped_threadpool::Pool,
-    key_mod: KeyCode,
+    commands: commands::Commands,
+    show_help: bool,
+    hud: hud::Hud,
+    config: config::Watcher,
+    history: history::History,
     btn_left: bool,
     btn_right: bool,
     btn_middle: bool,
     gravity_mod: usize,
-    gravity_f: f32,
-    latex_div: f32,
+    editor: editor::WellEditor,
+    brush: brush::Brush,
+    panel: panel::Panel,
+    graph: graph::Graph,
+    coloring: colormap::Coloring,
+    flow: flow::Flow,
+    surface: surface::Surface,
     avg_stats_vel: Vec<f32>,
     avg_stats_range: Vec<f32>,
     fast: usize,
+    // Last known position of the cursor, the simulation never asks ggez so
+    // that replays see the recorded one
+    mouse: vec::Vec,
+    recorder: Option<replay::Recorder>,
+    player: Option<replay::Player>,
+    metrics: Option<metrics::Stream>,
 }
 
 impl MyGame {
-    pub fn new(ctx: &mut Context) -> MyGame {
-        use rand::Rng;
-
+    pub fn new(ctx: &mut Context, args: &cli::Args) -> MyGame {
             // graphics::set_fullscreen(ctx, ggez::conf::FullscreenType::True).unwrap();
 
-        // Load/create resources here: images, fonts, sounds, etc.
-        let mut agents = vec![];
         let (w, h) = graphics::drawable_size(ctx);
-        while agents.len() < AGENT_NUM {
-            agents.push(ag::Agent::new(
-                agents.len(),
-                vec::Vec {
-                    x: rand::thread_rng().gen_range(0.0, w),
-                    y: rand::thread_rng().gen_range(0.0, h),
-                },
-                vec::Vec {
-                    x: 0.0,//rand::thread_rng().gen_range(-1.0, 1.0),
-                    y: 0.0,//rand::thread_rng().gen_range(-1.0, 1.0),
-                },
-            ))
+        // First, as it may move the logs to stderr
+        let metrics = args.metrics.as_ref().and_then(|path| match metrics::Stream::create(path, args.metrics_every) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                utils::log("error", json!({ "file": path, "message": e }));
+                None
+            },
+        });
+        let player = args.replay.as_ref().and_then(|path| match replay::Player::load(path) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                utils::log("error", json!({ "file": path, "message": e }));
+                None
+            },
+        });
+        let seed = match &player {
+            Some(p) => p.header.seed,
+            None => args.seed.unwrap_or((utils::now() * 1e6) as u64),
+        };
+        utils::seed(seed);
+        let header = replay::Header { seed, w, h, agents: AGENT_NUM };
+        if let Some(p) = &player {
+            if p.header != header {
+                utils::log("replay_mismatch", json!({ "recorded": p.header, "current": header }));
+            }
+            utils::log("replay", json!({ "file": p.path, "seed": seed }));
         }
+        let recorder = args.record.as_ref().and_then(|path| match replay::Recorder::create(path, &header) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                utils::log("error", json!({ "file": path, "message": e }));
+                None
+            },
+        });
+
         let mut game = MyGame {
             frames: 0,
             frames_start: utils::now(),
-            agents,
-            latex: Latex2D::new(0.0, 0.0, 0.0),
+            sim: sim::Sim::new(w, h, AGENT_NUM, clock::Clock::new(SIM_DT, SIM_RATE)),
             btn_left: false,
             btn_right: false,
             btn_middle: false,
             gravity_mod: 0,
-            gravity_f: 1.0,
-            latex_div: 4.0,
+            editor: editor::WellEditor::new(),
+            brush: brush::Brush::new(params::Params::default().brush_size, AGENT_MAX),
+            panel: panel::Panel::new(),
+            graph: graph::Graph::new(),
+            coloring: colormap::Coloring::default(),
+            flow: flow::Flow::default(),
+            surface: surface::Surface::default(),
             avg_stats_vel: vec![],
             avg_stats_range: vec![],
             fast: 0,
-            key_mod: KeyCode::F,
+            commands: commands::Commands::new(),
+            show_help: false,
+            hud: hud::Hud::new(),
+            config: config::Watcher::new(config::CONFIG_FILE),
+            history: history::History::new(HISTORY_EVERY, HISTORY_LEN),
+            mouse: vec::Vec::new(),
+            recorder,
+            player,
+            metrics,
             // pool: scoped_threadpool::Pool::new(8),
         };
 
-        game.adjust_latex_div(ctx);
+        game.reload_config();
+
+        game.adjust_latex_div();
 
         game
     }
@@ -139,97 +231,63 @@ impl MyGame {
         self.frames as f32 / (utils::now() - self.frames_start) as f32
     }
 
-    pub fn adjust_latex_div(&mut self, ctx: &mut Context) {
-        let mut min: Option<(f64, f32)> = None;
-        let ag = self.agents.clone();
-        for ld in 10..70 {
-            self.agents = ag.clone();
-            self.latex_div = ld as f32;
-            // Boot up
-            self.update(ctx).expect("no update");
-
-            // Measure
-            let t_start = utils::now();
-            for _ in 0..3 {
-                self.update(ctx).expect("no update");
-            }
-            let t_diff = utils::now() - t_start;
-
-            // Compare
-            println!("ld {}: {:.4}", ld, t_diff);
-            if !min.is_none() && min.unwrap().0 < t_diff {
-                break
-            }
-            if min.is_none() || min.unwrap().0 > t_diff {
-                min = Some((t_diff, ld as f32));
-            }
-        }
-        println!("best latex div: {:?}", min.unwrap());
-        self.agents = ag;
-        self.latex_div = min.unwrap().1 + 6.0;
+    pub fn adjust_latex_div(&mut self) {
+        // The best value depends on the machine, replays get the recorded one
+        if self.player.is_some() { return; }
+        self.sim.tune_latex_div();
+        self.record(replay::Input::LatexDiv { value: self.sim.latex_div });
         self.restart_fps();
     }
 
-    pub fn update_latex(&mut self, w: f32, h: f32) {
-        let mut latex = Latex2D::new(w / 2.0 / self.latex_div, w, h);
-        let _t0 = utils::now();
-        self.agents.iter().for_each(|x| latex.add((x.pos.x, x.pos.y), x.clone()));
-        // println!("latex:   {:.3}", utils::now() - _t0);
-        self.latex = latex
+    pub fn set_fast(&mut self, fast: usize) {
+        self.fast = fast;
+        self.sim.clock.rate = SIM_RATE * (self.fast*2).max(1) as f32;
     }
 }
 
 impl EventHandler for MyGame {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let (w, h) = graphics::drawable_size(ctx);
-        let pos = ggez::input::mouse::position(ctx);
-
-        for _ in 0..(self.fast*2).max(1) {
-            let mut tim = utils::Timer::new("UPDATE");
-            self.update_latex(w, h);
-            tim.tick("latex updated");
-
-            let _dx = (self.frames as f32 * 0.03).cos() * 0.3;
-            let _dy = (self.frames as f32 * 0.03).sin() * 0.3;
-
-            let update = ag::Update {
-                w,
-                h,
-                agents: &self.latex,
-                gravity_f: self.gravity_f,
-                gravity: match self.gravity_mod {
-                    1 => vec![
-                        vec::Vec::new_from(w*0.5, h*0.5),
-                    ],
-                    2 => vec![
-                        vec::Vec::new_from(w*0.5, h*0.5),
-                        vec::Vec::new_from(w*(0.5 + _dx), h*(0.5 + _dy)),
-                    ],
-                    3 => vec![
-                        vec::Vec::new_from(w*0.5, h*0.5),
-                        vec::Vec::new_from(pos.x, pos.y),
-                    ],
-                    4 => vec![
-                        vec::Vec::new_from(pos.x, pos.y),
-                    ],
-                    _ => vec![]
-
-                 },
-            };
-
-            self.agents.par_iter_mut().for_each(|x| x.update(&update));
+        self.reload_config();
+        let mut steps = self.sim.clock.advance();
+        if let Some(left) = self.replay_inputs(ctx) {
+            // Inputs are applied between frames, as when they were recorded
+            steps = steps.min(left as usize);
+        }
+        for _ in 0..steps {
+            if (self.btn_left || self.btn_right) && !self.editor.enabled {
+                let (w, h) = graphics::drawable_size(ctx);
+                let p = self.mouse;
+                let period = self.sim.fields.period(w, h);
+                if self.btn_right && self.brush.tool == brush::Tool::Paint {
+                    self.brush.pick_color(&self.sim.latex, p, period);
+                } else if self.brush.apply(&mut self.sim.agents, &self.sim.latex, p, !self.btn_left, self.sim.clock.dt, period) {
+                    self.sim.update_latex();
+                }
+            }
+            // Stepping from a past state replaces its future
+            self.history.branch();
+            self.sim.step(self.mouse);
+            self.sim.clock.tick();
             self.frames += 1;
-
-            tim.tick("agents updated");
-            tim.show();
+            if let Some(m) = self.metrics.as_mut() {
+                m.step(&self.sim);
+            }
+            self.graph.step(&self.sim);
+            if self.sim.clock.steps.is_multiple_of(self.history.every) {
+                self.history.push(self.snapshot());
+            }
+        }
+        if let Some(r) = self.recorder.as_mut() {
+            r.flush();
         }
         // println!("update:  {:.3}", utils::now() - _t0);
-        // self.agents.remove(0);
+        // self.sim.agents.remove(0);
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let mut tim = utils::Timer::new("DRAW");
+        let frame = profiler::scope("draw");
+        let p = profiler::scope("stats");
 
         let (w, h) = graphics::drawable_size(ctx);
 
@@ -241,18 +299,10 @@ impl EventHandler for MyGame {
         // Draw bbackground
         let mut mb_bg = &mut graphics::MeshBuilder::new();
         mb_bg.rectangle(graphics::DrawMode::fill(), graphics::Rect::new(0.0, 0.0, w, h),
-                graphics::Color::new(0.0, 0.0, 0.0, 0.97));
+                graphics::Color::new(0.0, 0.0, 0.0, self.sim.params.trail_alpha));
 
         // Get stats
-        let max_speed: f32 = self.agents.par_iter()
-            .fold(|| 0.0, |v: f32, x| v.max(x.s_vel))
-            .reduce(|| 0.0, |v: f32, x| v.max(x));
-        let mut col: [f32; 3] = self.agents.par_iter()
-            .fold(|| [0.0, 0.0, 0.0], |v, x| utils::sum(&v, &x.color))
-            .reduce(|| [0.0, 0.0, 0.0], |v, x| utils::sum(&v, &x));
-        utils::softmax_fast(&mut col);
-
-        tim.tick("done stats done");
+        let (max_speed, col) = self.sim.stats();
 
 
         let mut stats_mesh = ggez::graphics::MeshBuilder::new();
@@ -275,114 +325,186 @@ impl EventHandler for MyGame {
         }
 
 
-        tim.tick("draw stats done");
-
         self.avg_stats_vel.push(max_speed);
-        if self.avg_stats_vel.len() > ST_LEN { self.avg_stats_vel.remove(0); }
+        if self.avg_stats_vel.len() > sim::ST_LEN { self.avg_stats_vel.remove(0); }
         let max_speed = utils::avg(&self.avg_stats_vel);
 
-        let max_range: f32 = self.agents.par_iter()
+        let max_range: f32 = self.sim.agents.par_iter()
             .fold(|| 0.0, |v: f32, x| v.max(x.s_in_range as f32))
             .reduce(|| 0.0, |v: f32, x| v.max(x));
         self.avg_stats_range.push(max_range as f32);
-        if self.avg_stats_range.len() > ST_LEN { self.avg_stats_range.remove(0); }
+        if self.avg_stats_range.len() > sim::ST_LEN { self.avg_stats_range.remove(0); }
         let max_range = utils::avg(&self.avg_stats_range);
 
-        tim.tick("drew stats");
-
+        drop(p);
 
         // Draw agents
-        let _t0 = utils::now();
+        let p = profiler::scope("agents");
         let mut mb = &mut graphics::MeshBuilder::new();
-        self.agents.iter().for_each(|x| x.draw(ctx, &mut mb, &mut mb_bg, max_speed, max_range));
-        tim.tick("drew agents");
-
+        let alpha = self.sim.clock.alpha();
+        // Brushes and edits move agents between steps, `sim.latex` lags behind
+        let latex = self.sim.neighbours();
+        // The surface replaces the agents, it is drawn over the background
+        if !self.surface.enabled {
+            match self.coloring.colors(&self.sim, &latex) {
+                Some(colors) => self.sim.agents.iter().zip(colors).for_each(|(x, c)| x.draw_rgba(ctx, mb, c, alpha)),
+                None => self.sim.agents.iter().for_each(|x| x.draw(ctx, &mut mb, &mut mb_bg, max_speed, max_range, alpha)),
+            }
+        }
+        drop(p);
 
 
         // Draw background and foreground
+        let p = profiler::scope("meshes");
         let mb_bg = mb_bg.build(ctx).unwrap();
         graphics::draw(ctx, &mb_bg, graphics::DrawParam::new()).unwrap();
-        let mb = mb.build(ctx).unwrap();
-        graphics::draw(ctx, &mb, graphics::DrawParam::new()).unwrap();
+        if self.surface.enabled {
+            self.surface.draw(ctx, &self.sim, &latex)?;
+        } else {
+            let mb = mb.build(ctx).unwrap();
+            graphics::draw(ctx, &mb, graphics::DrawParam::new()).unwrap();
+        }
         let stats_mesh = stats_mesh.build(ctx).unwrap();
         graphics::draw(ctx, &stats_mesh, graphics::DrawParam::new()).unwrap();
+        let mouse = ggez::nalgebra::Point2::new(self.mouse.x, self.mouse.y);
+        if !self.editor.enabled {
+            let col = graphics::Color::new(self.brush.color[0], self.brush.color[1], self.brush.color[2], 0.4);
+            let ring = graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(1.0), mouse, self.brush.radius, 0.5, col)?;
+            graphics::draw(ctx, &ring, graphics::DrawParam::new())?;
+        }
+        if !self.sim.wells.is_empty() {
+            let mut mb_wells = graphics::MeshBuilder::new();
+            self.editor.draw(&self.sim.wells, self.sim.clock.time as f32, vec::Vec::new_from(mouse.x, mouse.y), &mut mb_wells);
+            let mb_wells = mb_wells.build(ctx).unwrap();
+            graphics::draw(ctx, &mb_wells, graphics::DrawParam::new()).unwrap();
+        }
+        self.flow.draw(ctx, &self.sim, &latex)?;
         // println!("prebuild:   {:.3}", utils::now() - _t0);
 
         // println!("draw:       {:.3}", utils::now() - _t0);
         // println!("build:      {:.3}", utils::now() - _t0);
 
-        // let _t0 = utils::now();
-        tim.tick("drawed bg and fg");
+        drop(p);
 
+        let p = profiler::scope("hud");
+        let hud = self.hud_sections(ctx);
+        self.hud.draw(ctx, &hud)?;
+        self.panel.draw(ctx, &self.sim.params)?;
+        self.graph.draw(ctx)?;
+        self.coloring.draw_legend(ctx)?;
+        if self.show_help {
+            self.draw_help(ctx)?;
+        }
+        drop(p);
 
+        let p = profiler::scope("present");
         graphics::present(ctx)?;
-        tim.tick("presented");
+        drop(p);
         // println!("present:    {:.3}", utils::now() - _t0);
 
-        print!("{}[2J", 27 as char);
-        println!("FPS:  DRAW = {:.2}  UPDATE = {:.2}", ggez::timer::fps(ctx), self.get_fps());
-        tim.show();
+        drop(frame);
+        profiler::frame();
         if utils::now() > self.frames_start + 1.0 {
             self.restart_fps();
         }
         Ok(())
     }
-    fn mouse_motion_event(
-        &mut self,
-        _ctx: &mut Context,
-        x: f32,
-        y: f32,
-        dx: f32,
-        dy: f32
-    ) {
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, dx: f32, dy: f32) {
+        self.live_input(ctx, replay::Input::MouseMove { x, y, dx, dy });
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: ggez::input::mouse::MouseButton, x: f32, y: f32) {
+        if let Some(button) = replay::button_name(button) {
+            self.live_input(ctx, replay::Input::MouseDown { button, x, y });
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: ggez::input::mouse::MouseButton, x: f32, y: f32) {
+        if let Some(button) = replay::button_name(button) {
+            self.live_input(ctx, replay::Input::MouseUp { button, x, y });
+        }
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) {
+        self.live_input(ctx, replay::Input::Wheel { y });
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, key: KeyCode, mods: KeyMods, _: bool) {
+        let key = commands::binding_name(&commands::Binding { key, mods });
+        self.live_input(ctx, replay::Input::Key { key });
+    }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        self.stop_recording();
+        false
+    }
+}
+
+impl MyGame {
+    fn mouse_motion(&mut self, _ctx: &mut Context, x: f32, y: f32, dx: f32, dy: f32) {
+        self.mouse = vec::Vec::new_from(x, y);
+        if self.panel.dragging() {
+            let (w, _) = graphics::drawable_size(_ctx);
+            if let Some(name) = self.panel.drag(&mut self.sim.params, w, x) {
+                self.param_changed(name);
+            }
+            return;
+        }
         let p = vec::Vec::new_from(x, y);
         let d = vec::Vec::new_from(dx, dy);
-        let radius = BRUSH_SIZE;
-        if self.btn_left {
-            let agents: Vec<usize> = self.latex.get((x, y), radius).iter().map(|x| x.id).collect();
-            // let mut i: Vec<usize> = Vec::new();
-            // let mut ids = std::collections::HashSet::new();
-            agents.iter().for_each(|id| {
-                // ids.insert(id);
-                let x = self.agents.get_mut(*id).expect("element in latex too much");
-                let dist = x.pos.dist_mod(&p, self.latex.w, self.latex.h);
-                if dist > radius { return; }
-                let mut d = d.clone();
-                // d.mul(2.0);
-                d.mul(( 1.0 - dist / radius) * 0.1);
-                x.vel.add(&d);
-                // x.pos.add(&d);
-            });
-            // self.agents.retain(|a| !ids.contains(&a.id));
-        }
-    }
-
-    fn mouse_button_down_event(
-        &mut self,
-        _ctx: &mut Context,
-        _button: ggez::input::mouse::MouseButton,
-        _x: f32,
-        _y: f32
-    ) {
+        if self.editor.enabled {
+            if self.btn_left {
+                self.editor.drag(&mut self.sim.wells, d);
+                self.sim.energy.reset();
+            }
+        } else if self.btn_left && self.brush.tool == brush::Tool::Push {
+            let period = self.sim.fields.period(self.sim.latex.w, self.sim.latex.h);
+            self.brush.push(&mut self.sim.agents, &self.sim.latex, p, d, period);
+        }
+    }
+
+    fn mouse_button_down(&mut self, _ctx: &mut Context, _button: ggez::input::mouse::MouseButton, _x: f32, _y: f32) {
         use ggez::input::mouse::MouseButton as mb;
+        self.mouse = vec::Vec::new_from(_x, _y);
+        let (w, _) = graphics::drawable_size(_ctx);
+        if self.panel.contains(&mut self.sim.params, w, _x, _y) {
+            if _button == mb::Left {
+                if let Some(name) = self.panel.press(&mut self.sim.params, w, _x, _y) {
+                    self.param_changed(name);
+                }
+            }
+            return;
+        }
+        if matches!(_button, mb::Left | mb::Right) {
+            self.history.checkpoint(self.snapshot());
+        }
+        if self.editor.enabled {
+            let p = vec::Vec::new_from(_x, _y);
+            let time = self.sim.clock.time as f32;
+            match _button {
+                mb::Left => self.editor.press(&mut self.sim.wells, time, p, gravity::strength(self.sim.params.gravity_f)),
+                mb::Right => self.editor.remove(&mut self.sim.wells, time, p),
+                _ => {},
+            }
+            self.sim.energy.reset();
+        }
         match _button {
             mb::Left => self.btn_left = true,
             mb::Right => self.btn_right = true,
             mb::Middle => {
                 self.btn_middle = true;
-                self.adjust_latex_div(_ctx);
+                self.adjust_latex_div();
             },
             mb::Other(_) => {},
         }
     }
-    fn mouse_button_up_event(
-        &mut self,
-        _ctx: &mut Context,
-        _button: ggez::input::mouse::MouseButton,
-        _x: f32,
-        _y: f32
-    ) {
+    fn mouse_button_up(&mut self, _button: ggez::input::mouse::MouseButton, _x: f32, _y: f32) {
         use ggez::input::mouse::MouseButton as mb;
+        self.mouse = vec::Vec::new_from(_x, _y);
+        if self.editor.enabled {
+            self.editor.release();
+        }
+        self.panel.release();
         match _button {
             mb::Left => self.btn_left = false,
             mb::Right => self.btn_right = false,
@@ -390,116 +512,560 @@ impl EventHandler for MyGame {
             mb::Other(_) => {},
         }
     }
-    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, _y: f32) {
-        self.agents.par_iter_mut().for_each(|x| {
-            x.pos_w+= _y;
-        });
-        println!("pos_w set to {}", self.agents[0].pos_w);
+    fn mouse_wheel(&mut self, _ctx: &mut Context, _y: f32) {
+        let pos = self.mouse;
+        let (w, _) = graphics::drawable_size(_ctx);
+        if self.panel.contains(&mut self.sim.params, w, pos.x, pos.y) {
+            if let Some(name) = self.panel.scroll(&mut self.sim.params, w, pos.x, pos.y, _y) {
+                self.param_changed(name);
+            }
+            return;
+        }
+        if self.editor.enabled {
+            let time = self.sim.clock.time as f32;
+            self.editor.scroll(&mut self.sim.wells, time, pos, _y);
+            if let Some(w) = self.editor.selected.and_then(|i| self.sim.wells.get(i)) {
+                utils::log("well_strength", json!({ "strength": w.strength }));
+            }
+            self.sim.energy.reset();
+            return;
+        }
+        // Agents take pos_w from the params every step, so the wheel tunes its base
+        if let Some(name) = self.sim.params.nudge("pos_w_base", _y * panel::WHEEL_STEP) {
+            self.param_changed(name);
+            utils::log("pos_w_base", json!({ "pos_w_base": self.sim.params.pos_w_base }));
+        }
     }
 
+    // Inputs from the window, while replaying only the ones that don't touch
+    // the simulation go through
+    fn live_input(&mut self, ctx: &mut Context, input: replay::Input) {
+        use commands::Action;
+        if self.player.is_some() {
+            if let replay::Input::Key { key } = &input {
+                let b = commands::parse_binding(key);
+                if let Some(a @ (Action::Quit | Action::Help | Action::Hud | Action::Graph | Action::ColorMode | Action::Flow | Action::Surface | Action::Pause | Action::Trace | Action::ExportSvg)) = b.ok().and_then(|b| self.commands.lookup(b.key, b.mods)) {
+                    self.run(ctx, a, None, KeyMods::NONE);
+                }
+            }
+            return;
+        }
+        self.record(input.clone());
+        self.input(ctx, input);
+    }
 
-    fn key_down_event(&mut self, _ctx: &mut Context, key: KeyCode, mods: KeyMods, _: bool) {
-        let rounds = if mods.contains(KeyMods::SHIFT | KeyMods::CTRL) {
-            1000
-        } else {
-            100
-        };
-        match key {
-            KeyCode::F |
-            KeyCode::A |
-            KeyCode::R |
-            KeyCode::B |
-            KeyCode::G |
-            KeyCode::Z |
-            KeyCode::X |
-            KeyCode::Escape => {
-                self.key_mod = key;
-            },
-            _  => { }
-        }
-
-        let input_num = map!{
-            KeyCode::Key1 => 1,
-            KeyCode::Key2 => 2,
-            KeyCode::Key3 => 3,
-            KeyCode::Key4 => 4,
-            KeyCode::Key5 => 5,
-            KeyCode::Key6 => 6,
-            KeyCode::Key7 => 7,
-            KeyCode::Key8 => 8,
-            KeyCode::Key9 => 9,
-            KeyCode::Key0 => 0
-        };
-        let input_num = input_num.get(&key);
+    fn record(&mut self, input: replay::Input) {
+        if let Some(r) = self.recorder.as_mut() {
+            r.record(self.sim.clock.steps, &input);
+        }
+    }
 
-        println!("keymod: {:?}, input_num: {:?}", self.key_mod, input_num);
+    fn stop_recording(&mut self) {
+        let hash = replay::hash(&self.sim.agents);
+        if let Some(mut r) = self.recorder.take() {
+            r.record(self.sim.clock.steps, &replay::Input::End { hash });
+            r.flush();
+            utils::log("recorded", json!({ "file": r.path, "step": self.sim.clock.steps, "hash": hash }));
+        }
+    }
 
-        match self.key_mod {
-            // Quit if Shift+Ctrl+Q is pressed.
-            KeyCode::Escape => {
-                event::quit(_ctx);
-            }
-            KeyCode::A => {
-                println!("making one aggressive");
-                for _ in 0..rounds {
-                    let s = self.agents.len();
-                    self.agents.get_mut(utils::rand_usize(s)).unwrap().color = [1.0, 0.0, 0.0];
+    fn input(&mut self, ctx: &mut Context, input: replay::Input) {
+        use replay::Input;
+        match input {
+            Input::MouseMove { x, y, dx, dy } => self.mouse_motion(ctx, x, y, dx, dy),
+            Input::MouseDown { button, x, y } => {
+                if let Some(b) = replay::parse_button(&button) { self.mouse_button_down(ctx, b, x, y) }
+            },
+            Input::MouseUp { button, x, y } => {
+                if let Some(b) = replay::parse_button(&button) { self.mouse_button_up(b, x, y) }
+            },
+            Input::Wheel { y } => self.mouse_wheel(ctx, y),
+            Input::Key { key } => {
+                let b = match commands::parse_binding(&key) {
+                    Ok(b) => b,
+                    Err(_) => return,
+                };
+                if let Some((action, arg)) = self.commands.press(b.key, b.mods) {
+                    // A replay stops at its end rather than at the recorded quit
+                    if action == commands::Action::Quit && self.player.is_some() { return; }
+                    self.run(ctx, action, arg, b.mods);
                 }
-
-            }
-            KeyCode::R => {
-                println!("making one aggressive");
-                for _ in 0..rounds {
-                    let s = self.agents.len();
-                    self.agents.get_mut(utils::rand_usize(s)).unwrap().color = [1.0, 0.0, 0.0];
+            },
+            Input::LatexDiv { value } => {
+                self.sim.latex_div = value;
+                self.sim.update_latex();
+            },
+            Input::Config { table, changed } => {
+                match toml::from_str(&table) {
+                    Ok(table) => self.apply_config(table, changed),
+                    Err(e) => utils::log("error", json!({ "message": e.to_string() })),
                 }
+            },
+            Input::End { hash } => {
+                let now = replay::hash(&self.sim.agents);
+                utils::log("replay_end", json!({ "step": self.sim.clock.steps, "hash": now, "expected": hash, "identical": now == hash }));
+                self.player = None;
+                self.sim.clock.paused = true;
+            },
+        }
+    }
 
+    // Feeds the recorded inputs that are due, returns how many steps can run
+    // before the next one
+    fn replay_inputs(&mut self, ctx: &mut Context) -> Option<u64> {
+        loop {
+            let step = self.sim.clock.steps;
+            let input = self.player.as_mut()?.next(step);
+            match input {
+                Some(i) => self.input(ctx, i),
+                None => return self.player.as_ref()?.steps_left(step),
             }
-            KeyCode::B => {
-                println!("making one aggressive");
-                for _ in 0..rounds {
-                    let s = self.agents.len();
-                    self.agents.get_mut(utils::rand_usize(s)).unwrap().color = [0.0, 0.0, 1.0];
-                }
+        }
+    }
 
-            }
-            KeyCode::G => {
-                println!("making one green");
-                for _ in 0..rounds {
-                    let s = self.agents.len();
-                    self.agents.get_mut(utils::rand_usize(s)).unwrap().color = [0.0, 1.0, 0.0];
+    pub fn run(&mut self, ctx: &mut Context, action: commands::Action, arg: Option<usize>, mods: KeyMods) {
+        use commands::Action;
+        let rounds = if mods.contains(KeyMods::SHIFT | KeyMods::CTRL) {
+            1000
+        } else {
+            100
+        };
+        match action {
+            Action::Quit => {
+                self.stop_recording();
+                event::quit(ctx);
+            },
+            Action::Help => {
+                self.show_help = !self.show_help;
+            },
+            Action::Hud => {
+                self.hud.cycle();
+            },
+            Action::Pause => {
+                self.sim.clock.toggle_pause();
+                utils::log("pause", json!({ "paused": self.sim.clock.paused }));
+            },
+            Action::Step => {
+                self.sim.clock.step_once();
+                utils::log("step", json!({ "step": self.sim.clock.steps }));
+            },
+            Action::BrushSmaller | Action::BrushBigger => {
+                self.sim.params.brush_size = self.brush.radius * if action == Action::BrushSmaller { 0.8 } else { 1.25 };
+                // Nudging by nothing keeps it within the slider range
+                self.sim.params.nudge("brush_size", 0.0);
+                self.brush.radius = self.sim.params.brush_size;
+                utils::log("brush", json!({ "radius": self.brush.radius }));
+            },
+            Action::BrushWeaker | Action::BrushStronger => {
+                self.brush.strength *= if action == Action::BrushWeaker { 0.8 } else { 1.25 };
+                utils::log("brush", json!({ "strength": self.brush.strength }));
+            },
+            Action::Editor => {
+                self.editor.enabled = !self.editor.enabled;
+                utils::log("well_editor", json!({ "enabled": self.editor.enabled }));
+            },
+            Action::SaveWells => {
+                match gravity::save(WELLS_FILE, &self.sim.wells) {
+                    Ok(_) => utils::log("wells_saved", json!({ "file": WELLS_FILE, "count": self.sim.wells.len() })),
+                    Err(e) => utils::log("error", json!({ "file": WELLS_FILE, "message": e })),
                 }
-
-            }
-            KeyCode::F => {
-                println!("making fast");
-                match input_num {
-                    Some(f) => {
-                        self.fast = *f;
+            },
+            Action::LoadWells => {
+                self.history.checkpoint(self.snapshot());
+                match gravity::load(WELLS_FILE) {
+                    Ok(wells) => {
+                        utils::log("wells_loaded", json!({ "file": WELLS_FILE, "count": wells.len() }));
+                        self.sim.wells = wells;
+                        self.sim.energy.reset();
                     },
-                    _ => {}
+                    Err(e) => utils::log("error", json!({ "file": WELLS_FILE, "message": e })),
                 }
-            }
-            KeyCode::Z => {
-                println!("gravity change");
-                match input_num {
-                    Some(f) => {
-                        self.gravity_mod = *f;
+            },
+            Action::Undo => {
+                match self.history.undo(self.sim.clock.steps) {
+                    Some(snap) => {
+                        utils::log("undo", json!({ "step": snap.step }));
+                        self.restore(&snap);
                     },
-                    _ => {}
+                    None => utils::log("undo", json!({ "step": null })),
                 }
-            }
-            KeyCode::X => {
-                println!("gravity force");
-                match input_num {
-                    Some(f) => {
-                        self.gravity_f = *f as f32;
+            },
+            Action::ScrubBack | Action::ScrubForward | Action::Rewind => {
+                // Keep the live state so scrubbing forward can return to it
+                if self.history.cursor.is_none() {
+                    self.history.push(self.snapshot());
+                }
+                let snap = match action {
+                    Action::ScrubBack => self.history.scrub(-1),
+                    Action::ScrubForward => self.history.scrub(1),
+                    _ => self.history.oldest(),
+                }.cloned();
+                if let Some(snap) = snap {
+                    self.sim.clock.paused = true;
+                    self.restore(&snap);
+                    utils::log("scrub", json!({ "step": snap.step, "index": self.history.cursor }));
+                }
+            },
+            Action::Branch => {
+                if self.history.cursor.is_some() {
+                    self.history.branch();
+                    utils::log("branch", json!({ "step": self.sim.clock.steps }));
+                }
+                if self.sim.clock.paused {
+                    self.sim.clock.toggle_pause();
+                }
+            },
+            Action::Panel => {
+                self.panel.enabled = !self.panel.enabled;
+            },
+            Action::Graph => {
+                self.graph.enabled = !self.graph.enabled;
+            },
+            Action::ColorMode => {
+                if mods.contains(KeyMods::SHIFT) {
+                    self.coloring.map = self.coloring.map.next();
+                } else {
+                    self.coloring.mode = self.coloring.mode.next();
+                }
+                utils::log("color_mode", json!({ "mode": self.coloring.mode, "map": self.coloring.map }));
+            },
+            Action::Flow => {
+                if mods.contains(KeyMods::SHIFT) {
+                    self.flow.cell = if self.flow.cell > 10.0 { self.flow.cell / 2.0 } else { 40.0 };
+                } else {
+                    self.flow.overlay = self.flow.overlay.next();
+                }
+                utils::log("flow", json!({ "overlay": self.flow.overlay, "cell": self.flow.cell }));
+            },
+            Action::Surface => {
+                if mods.contains(KeyMods::SHIFT) {
+                    match self.surface.save(CONTOURS_FILE, &self.sim) {
+                        Ok(n) => utils::log("contours_saved", json!({ "file": CONTOURS_FILE, "contours": n })),
+                        Err(e) => utils::log("error", json!({ "file": CONTOURS_FILE, "message": e })),
+                    }
+                } else {
+                    self.surface.enabled = !self.surface.enabled;
+                    utils::log("surface", json!({ "enabled": self.surface.enabled }));
+                }
+            },
+            Action::SavePreset => {
+                match params::save(PRESET_FILE, &self.sim.params) {
+                    Ok(_) => utils::log("preset_saved", json!({ "file": PRESET_FILE })),
+                    Err(e) => utils::log("error", json!({ "file": PRESET_FILE, "message": e })),
+                }
+            },
+            Action::LoadPreset => {
+                match params::load(PRESET_FILE) {
+                    Ok(p) => {
+                        // Wells and brush keep their own tuning unless the preset changes them
+                        let old = std::mem::replace(&mut self.sim.params, p);
+                        if old.gravity_f != self.sim.params.gravity_f { self.param_changed("gravity_f"); }
+                        if old.brush_size != self.sim.params.brush_size { self.param_changed("brush_size"); }
+                        utils::log("preset_loaded", json!({ "file": PRESET_FILE }));
                     },
-                    _ => {}
+                    Err(e) => utils::log("error", json!({ "file": PRESET_FILE, "message": e })),
+                }
+            },
+            Action::Trace => {
+                match profiler::export_chrome(TRACE_FILE) {
+                    Ok(n) => utils::log("trace_saved", json!({ "file": TRACE_FILE, "events": n })),
+                    Err(e) => utils::log("error", json!({ "file": TRACE_FILE, "message": e.to_string() })),
                 }
+            },
+            Action::ExportSvg => {
+                let opts = svg::Options { arrows: mods.contains(KeyMods::SHIFT) };
+                let doc = svg::render(&self.sim, utils::avg(&self.avg_stats_vel), self.mouse, &opts);
+                match svg::save(SVG_FILE, &doc) {
+                    Ok(_) => utils::log("svg_saved", json!({ "file": SVG_FILE, "agents": self.sim.agents.len() })),
+                    Err(e) => utils::log("error", json!({ "file": SVG_FILE, "message": e })),
+                }
+            },
+            Action::Adaptive => {
+                self.sim.adaptive.enabled = !self.sim.adaptive.enabled;
+                utils::log("adaptive_dt", json!({ "enabled": self.sim.adaptive.enabled }));
+            },
+            Action::Integrator => {
+                self.sim.integrator = self.sim.integrator.next();
+                self.sim.energy.reset();
+                utils::log("integrator", json!({ "integrator": format!("{:?}", self.sim.integrator) }));
+            },
+            Action::PaintRed | Action::PaintGreen | Action::PaintBlue => {
+                let color = match action {
+                    Action::PaintRed => [1.0, 0.0, 0.0],
+                    Action::PaintGreen => [0.0, 1.0, 0.0],
+                    _ => [0.0, 0.0, 1.0],
+                };
+                utils::log("paint", json!({ "count": rounds, "color": color }));
+                self.history.checkpoint(self.snapshot());
+                let s = self.sim.agents.len();
+                if s == 0 { return; }
+                for _ in 0..rounds {
+                    self.sim.agents.get_mut(utils::rand_usize(s)).unwrap().color = color;
+                }
+            },
+            Action::Fast => {
+                if let Some(f) = arg {
+                    self.set_fast(f);
+                    utils::log("fast", json!({ "fast": self.fast, "rate": self.sim.clock.rate }));
+                }
+            },
+            Action::BrushTool => {
+                // 1-8 pick the tool, 9 the falloff and 0 the paint colour
+                match arg {
+                    Some(9) => self.brush.falloff = self.brush.falloff.next(),
+                    Some(0) => self.brush.color = [self.brush.color[2], self.brush.color[0], self.brush.color[1]],
+                    Some(f) => self.brush.select(f),
+                    None => {},
+                }
+                utils::log("brush", json!({
+                    "tool": format!("{:?}", self.brush.tool),
+                    "falloff": format!("{:?}", self.brush.falloff),
+                    "color": self.brush.color,
+                }));
+            },
+            Action::Fields => {
+                if let Some(f) = arg {
+                    let on = match f {
+                        1 => { self.sim.fields.gravity.enabled ^= true; self.sim.fields.gravity.enabled },
+                        2 => { self.sim.fields.wind.enabled ^= true; self.sim.fields.wind.enabled },
+                        3 => { self.sim.fields.vortices.enabled ^= true; self.sim.fields.vortices.enabled },
+                        4 => { self.sim.fields.noise.enabled ^= true; self.sim.fields.noise.enabled },
+                        5 => {
+                            self.sim.fields.boundary = match self.sim.fields.boundary {
+                                field::Boundary::Wrap => field::Boundary::Walls { restitution: 0.5 },
+                                field::Boundary::Walls { .. } => field::Boundary::Wrap,
+                            };
+                            self.sim.fields.boundary != field::Boundary::Wrap
+                        },
+                        _ => return,
+                    };
+                    utils::log("field", json!({ "field": f, "enabled": on }));
+                    self.sim.energy.reset();
+                }
+            },
+            Action::SlowMotion => {
+                if let Some(f) = arg {
+                    // 1 is real time, 2 half speed, ..., 0 a tenth
+                    self.sim.clock.time_scale = 1.0 / if f == 0 { 10.0 } else { f as f32 };
+                    utils::log("time_scale", json!({ "time_scale": self.sim.clock.time_scale }));
+                }
+            },
+            Action::GravityPreset => {
+                if let Some(f) = arg {
+                    self.history.checkpoint(self.snapshot());
+                    let (w, h) = graphics::drawable_size(ctx);
+                    self.gravity_mod = f;
+                    self.sim.wells = gravity::preset(f, w, h, gravity::strength(self.sim.params.gravity_f));
+                    self.sim.energy.reset();
+                    utils::log("gravity_mod", json!({ "gravity_mod": self.gravity_mod, "wells": self.sim.wells.len() }));
+                }
+            },
+            Action::GravityForce => {
+                if let Some(f) = arg {
+                    self.sim.params.gravity_f = f as f32;
+                    self.param_changed("gravity_f");
+                    utils::log("gravity_f", json!({ "gravity_f": self.sim.params.gravity_f }));
+                }
+            },
+        }
+    }
+
+    // Applies the sections of the config file that changed since it was last
+    // read. Every section is checked before anything is applied, so a bad
+    // edit leaves the simulation untouched.
+    fn reload_config(&mut self) {
+        // Replays get the config edits from the session file
+        if self.player.is_some() { return; }
+        let (table, changed) = match self.config.poll(utils::now()) {
+            None => return,
+            Some(Ok(x)) => x,
+            Some(Err(e)) => {
+                utils::log("config_rejected", json!({ "file": self.config.path, "message": e }));
+                return;
+            },
+        };
+        if let Ok(text) = toml::to_string(&table) {
+            self.record(replay::Input::Config { table: text, changed: changed.clone() });
+        }
+        self.apply_config(table, changed);
+    }
+
+    fn apply_config(&mut self, table: toml::value::Table, changed: Vec<String>) {
+        let file = self.config.path.clone();
+        let section = |name: &str| changed.iter().any(|k| k == name || k.starts_with(&format!("{}.", name)));
+        let checked = (|| -> Result<_, String> {
+            let cfg = config::Config::parse(&table)?;
+            let mut commands = None;
+            if section("bindings") {
+                let mut c = commands::Commands::new();
+                let errors = c.remap(&cfg.bindings);
+                if !errors.is_empty() { return Err(errors.join(", ")); }
+                commands = Some(c);
             }
-            _ => (),
+            let params = match cfg.params.as_ref().filter(|_| section("params")) {
+                Some(p) => {
+                    let (patch, names) = config::pick(p, "params", &changed);
+                    let mut p: params::Params = config::merge(&self.sim.params, &patch)?;
+                    p.validate()?;
+                    Some((p, names))
+                },
+                None => None,
+            };
+            let fields = match cfg.fields.as_ref().filter(|_| section("fields")) {
+                Some(f) => {
+                    let (patch, _) = config::pick(f, "fields", &changed);
+                    let f: field::Fields = config::merge(&self.sim.fields, &patch)?;
+                    f.validate()?;
+                    Some(f)
+                },
+                None => None,
+            };
+            let wells = cfg.wells.filter(|_| section("wells"));
+            if let Some(w) = wells.as_ref() { gravity::validate(w)?; }
+            let render = config::Render::changed(&table, &changed)?;
+            if render.as_ref().and_then(|r| r.hud).is_some_and(|v| v >= hud::LEVELS) {
+                return Err(format!("render.hud must be below {}", hud::LEVELS));
+            }
+            if let Some(names) = render.as_ref().and_then(|r| r.graph_metrics.as_ref()) {
+                graph::check(names)?;
+            }
+            let looks = match render.as_ref() {
+                Some(r) => Some((r.coloring(&self.coloring)?, r.flow(&self.flow)?, r.surface(&self.surface)?)),
+                None => None,
+            };
+            Ok((commands, params, fields, wells, render, looks))
+        })();
+
+        let (commands, params, fields, wells, render, looks) = match checked {
+            Ok(x) => x,
+            Err(e) => {
+                utils::log("config_rejected", json!({ "file": file, "message": e }));
+                return;
+            },
+        };
+        if let Some(c) = commands { self.commands = c; }
+        if let Some((p, names)) = params {
+            self.sim.params = p;
+            names.iter().for_each(|n| self.param_changed(n));
+        }
+        if let Some(f) = fields {
+            self.sim.fields = f;
+            self.sim.energy.reset();
+        }
+        if let Some(w) = wells {
+            // The list has no keys to merge by, so it replaces the wells,
+            // including those placed in the editor since
+            utils::log("wells_replaced", json!({ "file": file, "before": self.sim.wells.len(), "after": w.len() }));
+            self.sim.wells = w;
+            self.editor.selected = None;
+            self.sim.energy.reset();
+        }
+        if let Some(r) = render {
+            if let Some(v) = r.hud { self.hud.verbosity = v; }
+            if let Some(v) = r.panel { self.panel.enabled = v; }
+            if let Some(v) = r.help { self.show_help = v; }
+            if let Some(v) = r.graph { self.graph.enabled = v; }
+            if let Some(v) = r.graph_metrics { self.graph.shown = v; }
+        }
+        if let Some((c, f, s)) = looks {
+            self.coloring = c;
+            self.flow = f;
+            self.surface = s;
+        }
+        self.config.accept(table);
+        if !changed.is_empty() {
+            utils::log("config_reloaded", json!({ "file": file, "changed": changed }));
+        }
+    }
+
+    fn snapshot(&self) -> history::Snapshot {
+        history::Snapshot::new(self.sim.clock.steps, self.sim.clock.time, &self.sim.agents, &self.sim.wells)
+    }
+
+    fn restore(&mut self, snap: &history::Snapshot) {
+        self.sim.agents = snap.agents();
+        self.sim.wells = snap.wells.clone();
+        self.sim.clock.steps = snap.step;
+        self.sim.clock.time = snap.time;
+        self.editor.selected = None;
+        self.sim.energy.reset();
+        self.sim.update_latex();
+    }
+
+    // Parameters that live outside of `Params` follow it here
+    fn param_changed(&mut self, name: &str) {
+        match name {
+            "brush_size" => self.brush.radius = self.sim.params.brush_size,
+            _ => self.sim.param_changed(name),
+        }
+    }
+
+    // Current value shown next to a command in the help overlay
+    fn command_value(&self, action: commands::Action) -> Option<String> {
+        use commands::Action;
+        Some(match action {
+            Action::Hud => format!("verbosity = {}", self.hud.verbosity),
+            Action::Graph => self.graph.shown.join(", "),
+            Action::ColorMode => format!("{:?}, {:?}", self.coloring.mode, self.coloring.map),
+            Action::Flow => format!("{:?}, cell = {}", self.flow.overlay, self.flow.cell),
+            Action::Surface => format!("{}, iso = {}", self.surface.enabled, self.surface.iso),
+            Action::Pause => self.sim.clock.paused.to_string(),
+            Action::SlowMotion => self.sim.clock.time_scale.to_string(),
+            Action::Fast => format!("fast = {}", self.fast),
+            Action::Integrator => format!("{:?}", self.sim.integrator),
+            Action::Adaptive => self.sim.adaptive.enabled.to_string(),
+            Action::GravityPreset => format!("gravity_mod = {}", self.gravity_mod),
+            Action::GravityForce => format!("gravity_f = {}", self.sim.params.gravity_f),
+            Action::Editor => self.editor.enabled.to_string(),
+            Action::LoadWells | Action::SaveWells => format!("{} wells", self.sim.wells.len()),
+            Action::BrushTool => format!("{:?}, {:?}", self.brush.tool, self.brush.falloff),
+            Action::BrushSmaller | Action::BrushBigger => format!("radius = {}", self.brush.radius),
+            Action::BrushWeaker | Action::BrushStronger => format!("strength = {}", self.brush.strength),
+            _ => return None,
+        })
+    }
+
+    fn hud_sections(&self, ctx: &mut Context) -> Vec<Vec<String>> {
+        let mut rates = vec![
+            format!("FPS: draw {:.1}  update {:.1}", ggez::timer::fps(ctx), self.get_fps()),
+            format!("agents: {}  step: {}  t: {:.1}{}", self.sim.agents.len(), self.sim.clock.steps, self.sim.clock.time,
+                if self.sim.clock.paused { "  PAUSED" } else { "" }),
+            format!("rate: {}/s  time scale: {}", self.sim.clock.rate, self.sim.clock.time_scale),
+        ];
+        if let Some(p) = &self.player {
+            rates.push(format!("replaying {}", p.path));
+        }
+        if self.sim.adaptive.enabled {
+            rates.push(format!("dt: {:.4} x {} substeps", self.sim.adaptive.last_dt, self.sim.adaptive.last_substeps));
+        }
+        if let Some(i) = self.history.cursor {
+            rates.push(format!("history: snapshot {}/{}  (Return to branch)", i + 1, self.history.len()));
+        }
+        let params = vec![
+            format!("integrator: {:?}  dt: {}", self.sim.integrator, self.sim.clock.dt),
+            format!("gravity_mod: {}  gravity_f: {}  wells: {}", self.gravity_mod, self.sim.params.gravity_f, self.sim.wells.len()),
+            format!("brush: {:?} r={} s={}", self.brush.tool, self.brush.radius, self.brush.strength),
+            format!("fast: {}  latex_div: {}", self.fast, self.sim.latex_div),
+        ];
+        let mut timers = vec![format!("{:<22} {:>8} {:>8} {:>8} {:>6}", "ms per frame", "min", "mean", "max", "calls")];
+        timers.extend(profiler::stats().iter().map(|x| format!("{:<22} {:>8.3} {:>8.3} {:>8.3} {:>6.1}",
+            "  ".repeat(x.depth) + &x.name, x.min * 1000.0, x.mean * 1000.0, x.max * 1000.0, x.calls)));
+        vec![rates, params, timers]
+    }
+
+    fn draw_help(&self, ctx: &mut Context) -> GameResult<()> {
+        let mut lines = self.commands.help(|a| self.command_value(a));
+        if let Some(a) = self.commands.pending() {
+            lines.push(String::new());
+            lines.push(format!("digits go to: {}", self.commands.get(a).description));
         }
+        let text = graphics::Text::new(lines.join("\n"));
+        let (tw, th) = text.dimensions(ctx);
+        let bg = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
+            graphics::Rect::new(30.0, 40.0, tw as f32 + 20.0, th as f32 + 20.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.8))?;
+        graphics::draw(ctx, &bg, graphics::DrawParam::new())?;
+        graphics::draw(ctx, &text, graphics::DrawParam::new().dest(ggez::nalgebra::Point2::new(40.0, 50.0)))
     }
 }
 
@@ -535,6 +1101,661 @@ fn test_vec () {
     assert!((rel.dist(&v1) - 1.0).abs() < tol);
 }
 
+#[test]
+fn test_integrators () {
+    use integrator::{Integrator, State};
+    // Unit harmonic oscillator, exact solution is x = cos(t)
+    let spring = |s: &State| vec::Vec::new_from(-s.pos.x, -s.pos.y);
+    let dt = 0.05;
+    let n = 2000;
+    for int in [Integrator::Euler, Integrator::Verlet, Integrator::Leapfrog, Integrator::Rk4].iter() {
+        let mut s = State { pos: vec::Vec::new_from(1.0, 0.0), vel: vec::Vec::new() };
+        for _ in 0..n {
+            s = int.step(s, dt, spring);
+        }
+        let energy = 0.5 * (s.pos.x.powi(2) + s.vel.x.powi(2));
+        assert!((energy - 0.5).abs() < 0.025);
+        if *int == Integrator::Rk4 {
+            assert!((s.pos.x - (dt * n as f32).cos()).abs() < 0.001);
+        }
+    }
+}
+
+#[test]
+fn test_adaptive () {
+    let mut a = clock::Adaptive::new();
+    assert_eq!(a.substeps(1.0, 0.0, 0.0, 10.0), 1);
+    // Half the view range per substep at most
+    assert_eq!(a.substeps(1.0, 20.0, 0.0, 10.0), 4);
+    assert_eq!(a.last_dt, 0.25);
+    // sqrt(2 * 0.25 * 10 / acc) long substeps
+    assert_eq!(a.substeps(1.0, 0.0, 5.0, 10.0), 1);
+    assert_eq!(a.substeps(1.0, 0.0, 80.0, 10.0), 4);
+    assert_eq!(a.substeps(1.0, 20.0, 320.0, 10.0), 8);
+    assert!(!a.capped);
+    assert_eq!(a.substeps(1.0, 1e6, 0.0, 10.0), a.max_substeps);
+    assert_eq!(a.last_dt, 1.0 / a.max_substeps as f32);
+    assert!(a.capped);
+
+    // A push between steps is seen by the next one
+    let mut sim = sim::Sim::new(100.0, 100.0, 1, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.adaptive.enabled = true;
+    sim.agents[0].vel = vec::Vec::new_from(1e6, 0.0);
+    sim.step(vec::Vec::new());
+    assert_eq!(sim.adaptive.last_substeps, sim.adaptive.max_substeps);
+}
+
+#[test]
+fn test_wells () {
+    let wells = gravity::preset(2, 100.0, 100.0, gravity::strength(2.0));
+    let path = std::env::temp_dir().join("test_wells.toml");
+    let path = path.to_str().unwrap();
+    gravity::save(path, &wells).expect("cannot save");
+    let loaded = gravity::load(path).expect("cannot load");
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[1].path, wells[1].path);
+
+    // The default well matches the old hardcoded 0.2/(100+d^2) pull
+    let p = vec::Vec::new_from(20.0, 50.0);
+    let a = wells[0].accel(&p);
+    assert!((a.x - wells[0].strength * 30.0 / (100.0 + 900.0)).abs() < 1e-6);
+    // and the acceleration is minus the gradient of the potential
+    let e = 0.01;
+    let dx = (wells[0].potential(&vec::Vec::new_from(20.0 + e, 50.0)) - wells[0].potential(&p)) / e;
+    assert!((a.x + dx).abs() < 1e-3);
+
+    // The orbit is stretched like the world, as the old switch had it
+    let orbit = gravity::preset(2, 200.0, 100.0, 1.0)[1];
+    let quarter = std::f32::consts::FRAC_PI_2 / 0.03;
+    let p = orbit.at(0.0, vec::Vec::new()).pos;
+    assert!((p.x - 160.0).abs() < 1e-3 && (p.y - 50.0).abs() < 1e-3);
+    let p = orbit.at(quarter, vec::Vec::new()).pos;
+    assert!((p.x - 100.0).abs() < 1e-3 && (p.y - 80.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_editor () {
+    use editor::WellEditor;
+    let v = |x: f32, y: f32| vec::Vec::new_from(x, y);
+    let mut e = WellEditor::new();
+    let mut wells = vec![gravity::Well::new(v(20.0, 20.0), 1.0)];
+
+    // Pressing on empty space places a well, on a ring selects it
+    e.press(&mut wells, 0.0, v(80.0, 80.0), 0.5);
+    assert_eq!((wells.len(), e.selected), (2, Some(1)));
+    e.release();
+    e.press(&mut wells, 0.0, v(25.0, 22.0), 0.5);
+    assert_eq!((wells.len(), e.selected), (2, Some(0)));
+
+    // Dragging moves it until released
+    e.drag(&mut wells, v(5.0, -5.0));
+    assert_eq!((wells[0].pos.x, wells[0].pos.y), (25.0, 15.0));
+    e.release();
+    e.drag(&mut wells, v(5.0, -5.0));
+    assert_eq!((wells[0].pos.x, wells[0].pos.y), (25.0, 15.0));
+
+    // An orbiting well is picked where it is and dragged by its centre
+    wells[1] = wells[1].with_path(gravity::Path::Orbit { speed: 0.0, radius: v(10.0, 10.0), center: v(80.0, 80.0) });
+    e.press(&mut wells, 0.0, v(90.0, 80.0), 0.5);
+    assert_eq!(e.selected, Some(1));
+    e.drag(&mut wells, v(-10.0, 0.0));
+    assert_eq!(wells[1].at(0.0, v(0.0, 0.0)).pos.x, 80.0);
+    e.release();
+
+    // The wheel turns the selected attractor into a repeller
+    e.scroll(&mut wells, 0.0, v(0.0, 0.0), -30.0);
+    assert!(wells[1].strength < 0.0);
+    assert_eq!(wells[0].strength, 1.0);
+
+    // Wells following the mouse can't be grabbed
+    wells[0].path = gravity::Path::Mouse;
+    assert_eq!(WellEditor::pick(&wells, 0.0, v(25.0, 15.0)), None);
+    e.remove(&mut wells, 0.0, v(80.0, 80.0));
+    assert_eq!((wells.len(), e.selected), (1, None));
+}
+
+#[test]
+fn test_fields () {
+    use field::{Boundary, Fields};
+    let v = |x: f32, y: f32| vec::Vec::new_from(x, y);
+    let close = |a: vec::Vec, x: f32, y: f32| (a.x - x).abs() < 1e-6 && (a.y - y).abs() < 1e-6;
+    let mut f = Fields::new(100.0, 90.0);
+    assert!(f.validate().is_ok());
+    assert!(close(f.accel(&v(50.0, 10.0), &v(0.0, 0.0), 0.0), 0.0, 0.0));
+    assert_eq!(f.period(100.0, 90.0), (100.0, 90.0));
+
+    // Uniform gravity points down the screen at 90 degrees and the
+    // potential falls along it
+    f.gravity.enabled = true;
+    assert!(close(f.accel(&v(50.0, 10.0), &v(0.0, 0.0), 0.0), 0.0, 0.03));
+    assert!(f.potential(&v(50.0, 20.0)) < f.potential(&v(50.0, 10.0)));
+    f.gravity.enabled = false;
+
+    // Wind only inside its zones, pulling the velocity towards theirs
+    f.wind.enabled = true;
+    assert!(close(f.accel(&v(50.0, 10.0), &v(1.0, 0.0), 0.0), 0.02, 0.0));
+    assert!(close(f.accel(&v(50.0, 45.0), &v(1.0, 0.0), 0.0), 0.0, 0.0));
+    assert!(close(f.accel(&v(50.0, 80.0), &v(0.0, 1.0), 0.0), -0.04, -0.02));
+    f.wind.enabled = false;
+
+    // A vortex turns counter clockwise around its centre, weakly far away
+    f.vortices.enabled = true;
+    let r = f.vortices.list[0].radius;
+    assert!(close(f.accel(&v(60.0, 45.0), &v(0.0, 0.0), 0.0), 0.0, 10.0 / (100.0 + r * r)));
+    assert!(close(f.accel(&v(50.0, 45.0), &v(0.0, 0.0), 0.0), 0.0, 0.0));
+    f.vortices.enabled = false;
+
+    // Curl noise changes over time, has no divergence and stays around
+    // its strength
+    f.noise.enabled = true;
+    let p = v(37.0, 61.0);
+    let a = f.accel(&p, &v(0.0, 0.0), 0.0);
+    assert!(a.mag() > 0.0 && a.mag() < f.noise.strength * 4.0);
+    assert!((f.accel(&p, &v(0.0, 0.0), 500.0).x - a.x).abs() > 1e-6);
+    let h = 0.5;
+    let div = (f.accel(&v(p.x + h, p.y), &a, 0.0).x - f.accel(&v(p.x - h, p.y), &a, 0.0).x
+        + f.accel(&v(p.x, p.y + h), &a, 0.0).y - f.accel(&v(p.x, p.y - h), &a, 0.0).y) / (2.0 * h);
+    assert!(div.abs() < f.noise.strength / f.noise.scale * 0.1, "divergence {}", div);
+    f.noise.enabled = false;
+
+    // Walls bounce and don't wrap distances
+    f.boundary = Boundary::Walls { restitution: 0.5 };
+    assert_eq!(f.period(100.0, 90.0), (f32::INFINITY, f32::INFINITY));
+    let (mut pos, mut vel) = (v(-2.0, 95.0), v(-1.0, 2.0));
+    f.apply_boundary(&mut pos, &mut vel, 100.0, 90.0);
+    assert!(close(pos, 2.0, 85.0) && close(vel, 0.5, -1.0));
+    f.boundary = Boundary::Wrap;
+    let (mut pos, mut vel) = (v(105.0, -3.0), v(1.0, -1.0));
+    f.apply_boundary(&mut pos, &mut vel, 100.0, 90.0);
+    assert!(close(pos, 5.0, 87.0) && close(vel, 1.0, -1.0));
+
+    for bad in [
+        "[boundary]\nkind = \"walls\"\nrestitution = 1.5",
+        "[noise]\nscale = 0",
+        "[vortices]\nlist = [{ strength = 1.0, radius = 0.0, pos = { x = 1.0, y = 1.0 } }]",
+        "[wind]\nzones = [{ rect = [0.0, 0.0, -1.0, 5.0], coupling = 0.1, vel = { x = 1.0, y = 0.0 } }]",
+    ] {
+        let f: Fields = config::merge(&Fields::new(100.0, 90.0), &toml::from_str(bad).unwrap()).unwrap();
+        assert!(f.validate().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_brush () {
+    use brush::{Brush, Falloff, Tool};
+    let mut b = Brush::new(10.0, 20);
+    for (falloff, mid, edge) in [
+        (Falloff::Constant, 1.0, 1.0),
+        (Falloff::Linear, 0.5, 0.0),
+        (Falloff::Smooth, 0.5, 0.0),
+        (Falloff::Gaussian, (-1.0f32).exp(), (-4.0f32).exp()),
+    ] {
+        b.falloff = falloff;
+        assert_eq!(b.weight(0.0), 1.0);
+        assert!((b.weight(5.0) - mid).abs() < 1e-6);
+        assert!((b.weight(10.0) - edge).abs() < 1e-6);
+        assert_eq!(b.weight(10.5), 0.0);
+    }
+
+    // Erase takes everything under a strong brush and renumbers the rest,
+    // which keep their class
+    let period = (100.0, 100.0);
+    let mut agents: Vec<ag::Agent> = [(10.0, 10.0), (50.0, 50.0), (52.0, 50.0), (90.0, 90.0)].iter().enumerate()
+        .map(|(i, p)| ag::Agent::new(i, vec::Vec::new_from(p.0, p.1), vec::Vec::new()))
+        .collect();
+    let latex = |agents: &[ag::Agent]| {
+        let mut l = latex::Latex2D::new(10.0, 100.0, 100.0);
+        agents.iter().for_each(|x| l.add((x.pos.x, x.pos.y), *x));
+        l
+    };
+    b.tool = Tool::Erase;
+    b.falloff = Falloff::Constant;
+    b.strength = 100.0;
+    let l = latex(&agents);
+    assert!(b.apply(&mut agents, &l, vec::Vec::new_from(50.0, 50.0), false, 1.0, period));
+    assert_eq!(agents.iter().map(|x| (x.id, x.class, x.pos.x)).collect::<Vec<_>>(), vec![(0, 0, 10.0), (1, 3, 90.0)]);
+
+    // Spawn near a corner keeps the new agents in the world
+    b.tool = Tool::Spawn;
+    b.radius = 30.0;
+    let l = latex(&agents);
+    assert!(b.apply(&mut agents, &l, vec::Vec::new_from(2.0, 2.0), false, 1.0, period));
+    assert!(agents.len() > 2);
+    assert!(agents.iter().all(|x| x.pos.x >= 0.0 && x.pos.y >= 0.0 && x.pos.x < 100.0 && x.pos.y < 100.0));
+    assert!(agents.iter().enumerate().all(|(i, x)| x.id == i));
+
+    // but no more than the brush allows
+    b.strength = 1000.0;
+    let l = latex(&agents);
+    b.apply(&mut agents, &l, vec::Vec::new_from(50.0, 50.0), false, 1.0, period);
+    assert_eq!(agents.len(), 20);
+    let l = latex(&agents);
+    assert!(!b.apply(&mut agents, &l, vec::Vec::new_from(50.0, 50.0), false, 1.0, period));
+}
+
+#[test]
+fn test_commands () {
+    use commands::{Action, Commands, parse_binding};
+    let b = parse_binding("Ctrl+Shift+b").unwrap();
+    assert_eq!(b.key, KeyCode::B);
+    assert_eq!(b.mods, KeyMods::CTRL | KeyMods::SHIFT);
+    assert!(parse_binding("Hyper+B").is_err());
+
+    let mut c = Commands::new();
+    assert_eq!(c.press(KeyCode::Escape, KeyMods::NONE), Some((Action::Quit, None)));
+    // Digit commands wait for their argument and stay active
+    assert_eq!(c.press(KeyCode::F, KeyMods::NONE), None);
+    assert_eq!(c.press(KeyCode::Key3, KeyMods::NONE), Some((Action::Fast, Some(3))));
+    assert_eq!(c.press(KeyCode::Key1, KeyMods::NONE), Some((Action::Fast, Some(1))));
+    // Modifiers don't prevent a plain binding from matching
+    assert_eq!(c.press(KeyCode::B, KeyMods::CTRL | KeyMods::SHIFT), Some((Action::PaintBlue, None)));
+
+    let mut remap = std::collections::HashMap::new();
+    remap.insert("pause".to_string(), vec!["P".to_string()]);
+    remap.insert("nope".to_string(), vec!["Q".to_string()]);
+    assert_eq!(c.remap(&remap).len(), 1);
+    assert_eq!(c.press(KeyCode::P, KeyMods::NONE), Some((Action::Pause, None)));
+    assert_eq!(c.press(KeyCode::Space, KeyMods::NONE), None);
+}
+
+#[test]
+fn test_profiler () {
+    {
+        let _a = profiler::scope("test_outer");
+        let _b = profiler::scope("test_inner");
+    }
+    std::thread::spawn(|| { let _a = profiler::scope("test_outer"); }).join().unwrap();
+    profiler::frame();
+    let stats = profiler::stats();
+    let outer = stats.iter().find(|x| x.path == "test_outer").unwrap();
+    let inner = stats.iter().find(|x| x.path == "test_outer/test_inner").unwrap();
+    // Calls from both threads land in the same frame
+    assert_eq!(outer.calls, 2.0);
+    assert_eq!(inner.depth, 1);
+    assert!(outer.min <= outer.mean && outer.mean <= outer.max);
+}
+
+#[test]
+fn test_params () {
+    let mut p: params::Params = toml::from_str("weirdness = 2.0").unwrap();
+    assert_eq!(p.weirdness, 2.0);
+    assert_eq!(p.trail_alpha, params::Params::default().trail_alpha);
+    for (_, min, max, v) in p.sliders() {
+        assert!(min <= *v && *v <= max);
+    }
+    let back: params::Params = toml::from_str(&toml::to_string(&p).unwrap()).unwrap();
+    assert_eq!(back, p);
+
+    // A preset out of the slider ranges is refused when loaded
+    let path = std::env::temp_dir().join("fluid_test_params.toml");
+    let path = path.to_str().unwrap();
+    params::save(path, &p).expect("cannot save");
+    assert_eq!(params::load(path).expect("cannot load"), p);
+    p.brush_size = 1000.0;
+    params::save(path, &p).expect("cannot save");
+    assert!(params::load(path).is_err());
+    p.nudge("brush_size", 0.0);
+    assert_eq!(p.brush_size, 300.0);
+}
+
+#[test]
+fn test_config () {
+    let a: toml::value::Table = toml::from_str("[params]\nweirdness = 1.0\n[render]\nhud = 2").unwrap();
+    let b: toml::value::Table = toml::from_str("[params]\nweirdness = 1.5\n[fields.boundary]\nkind = \"walls\"\nrestitution = 0.5").unwrap();
+    assert_eq!(config::changed_keys(&a, &b), vec!["fields", "params.weirdness", "render"]);
+    let cfg = config::Config::parse(&b).unwrap();
+
+    // Partial sections only touch the keys they mention
+    let fields: field::Fields = config::merge(&field::Fields::new(100.0, 100.0), cfg.fields.as_ref().unwrap()).unwrap();
+    assert_eq!(fields.boundary, field::Boundary::Walls { restitution: 0.5 });
+    assert_eq!(fields.noise.scale, 150.0);
+    let params: params::Params = config::merge(&params::Params::default(), cfg.params.as_ref().unwrap()).unwrap();
+    assert_eq!(params.weirdness, 1.5);
+
+    assert!(config::Config::parse(&toml::from_str("[parmas]\nweirdness = 1.0").unwrap()).is_err());
+    assert!(config::merge(&params::Params::default(), &toml::from_str::<toml::Value>("weirdness = \"x\"").unwrap()).is_err());
+
+    // Editing one param leaves the others, and the wells, as they were tuned
+    let mut sim = sim::Sim::new(100.0, 100.0, 1, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.wells = vec![gravity::Well::new(vec::Vec::new_from(10.0, 10.0), 3.0), gravity::Well::new(vec::Vec::new_from(50.0, 50.0), -7.0)];
+    sim.params.drag_red = 0.5;
+    let old: toml::value::Table = toml::from_str("[params]\nweirdness = 1.0\ngravity_f = 2.0\ndrag_red = 0.001").unwrap();
+    let new: toml::value::Table = toml::from_str("[params]\nweirdness = 1.5\ngravity_f = 2.0\ndrag_red = 0.001").unwrap();
+    let changed = config::changed_keys(&old, &new);
+    let (patch, names) = config::pick(new.get("params").unwrap(), "params", &changed);
+    assert_eq!(names, vec!["weirdness"]);
+    sim.params = config::merge(&sim.params, &patch).unwrap();
+    names.iter().for_each(|n| sim.param_changed(n));
+    assert_eq!((sim.params.weirdness, sim.params.drag_red), (1.5, 0.5));
+    assert_eq!(sim.wells.iter().map(|w| w.strength).collect::<Vec<_>>(), vec![3.0, -7.0]);
+    sim.param_changed("gravity_f");
+    assert!(sim.wells.iter().all(|w| w.strength.abs() == gravity::strength(sim.params.gravity_f)));
+
+    // Fields too, and a section added as a whole brings all its keys
+    sim.fields.noise.scale = 80.0;
+    let (patch, _) = config::pick(cfg.fields.as_ref().unwrap(), "fields", &config::changed_keys(&a, &b));
+    sim.fields = config::merge(&sim.fields, &patch).unwrap();
+    assert_eq!((sim.fields.boundary, sim.fields.noise.scale), (field::Boundary::Walls { restitution: 0.5 }, 80.0));
+    let old: toml::value::Table = toml::from_str("[fields.noise]\nscale = 150.0\n[fields.boundary]\nkind = \"walls\"\nrestitution = 0.5").unwrap();
+    let new: toml::value::Table = toml::from_str("[fields.noise]\nscale = 150.0\n[fields.boundary]\nkind = \"walls\"\nrestitution = 0.8").unwrap();
+    let (patch, names) = config::pick(new.get("fields").unwrap(), "fields", &config::changed_keys(&old, &new));
+    assert_eq!(names, vec!["boundary.restitution"]);
+    sim.fields = config::merge(&sim.fields, &patch).unwrap();
+    assert_eq!((sim.fields.boundary, sim.fields.noise.scale), (field::Boundary::Walls { restitution: 0.8 }, 80.0));
+
+    // Render settings toggled from the keyboard survive an edit of another one
+    let old: toml::value::Table = toml::from_str("[render]\nhud = 2\ngraph = false\nflow = \"arrows\"").unwrap();
+    let new: toml::value::Table = toml::from_str("[render]\nhud = 2\ngraph = true\nflow = \"arrows\"").unwrap();
+    let r = config::Render::changed(&new, &config::changed_keys(&old, &new)).unwrap().unwrap();
+    assert_eq!((r.hud, r.graph, r.flow), (None, Some(true), None));
+    assert!(config::Render::changed(&new, &config::changed_keys(&a, &a)).unwrap().is_none());
+}
+
+#[test]
+fn test_history () {
+    let agents: Vec<ag::Agent> = (0..3).map(|i| ag::Agent::new(i, vec::Vec::new_from(i as f32, 0.0), vec::Vec::new())).collect();
+    let mut h = history::History::new(10, 3);
+    for step in [10, 20, 30, 40] {
+        h.push(history::Snapshot::new(step, step as f64, &agents, &[]));
+    }
+    assert_eq!(h.len(), 3);
+    assert_eq!(h.scrub(-1).unwrap().step, 30);
+    assert_eq!(h.scrub(-5).unwrap().step, 20);
+    // Pushing while scrubbing drops the snapshots after the cursor
+    h.push(history::Snapshot::new(21, 21.0, &agents, &[]));
+    assert_eq!(h.len(), 2);
+    assert_eq!(h.cursor, None);
+
+    h.checkpoint(history::Snapshot::new(25, 25.0, &agents, &[]));
+    assert_eq!(h.undo(25).unwrap().step, 25);
+    assert_eq!(h.undo(25).unwrap().step, 21);
+    let back = h.undo(21).unwrap().agents();
+    assert_eq!(back[2].pos, agents[2].pos);
+    assert_eq!(back[2].color, agents[2].color);
+
+    // Periodic snapshots after an edit don't hide it
+    let mut h = history::History::new(10, 10);
+    h.push(history::Snapshot::new(20, 20.0, &agents, &[]));
+    h.checkpoint(history::Snapshot::new(100, 100.0, &agents, &[]));
+    for step in [120, 150] {
+        h.push(history::Snapshot::new(step, step as f64, &agents, &[]));
+    }
+    assert_eq!(h.undo(160).unwrap().step, 100);
+    assert_eq!(h.undo(100).unwrap().step, 20);
+}
+
+#[test]
+fn test_replay () {
+    use replay::{Entry, Input};
+    let path = std::env::temp_dir().join("fluid_test_replay.jsonl");
+    let path = path.to_str().unwrap();
+    let header = replay::Header { seed: 7, w: 800.0, h: 600.0, agents: 10 };
+    let mut r = replay::Recorder::create(path, &header).unwrap();
+    r.record(0, &Input::Key { key: "Ctrl+Z".to_string() });
+    r.record(0, &Input::MouseDown { button: "left".to_string(), x: 1.0, y: 2.0 });
+    r.record(5, &Input::End { hash: 42 });
+    r.flush();
+
+    let mut p = replay::Player::load(path).unwrap();
+    assert_eq!(p.header, header);
+    assert_eq!(p.next(0), Some(Input::Key { key: "Ctrl+Z".to_string() }));
+    assert!(p.next(0).is_some());
+    assert_eq!(p.next(0), None);
+    assert_eq!(p.steps_left(2), Some(3));
+    assert_eq!(p.next(5), Some(Input::End { hash: 42 }));
+    assert!(serde_json::from_str::<Entry>("{\"step\": 1, \"kind\": \"nope\"}").is_err());
+}
+
+#[test]
+fn test_raster () {
+    let mut c = raster::Canvas::new(20, 10);
+    c.circle(5.0, 5.0, 3.0, [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!(c.pixels[5 * 20 + 5], [1.0, 0.0, 0.0]);
+    assert_eq!(c.pixels[5 * 20 + 15], [0.0, 0.0, 0.0]);
+    // Half of it is left by the trail
+    c.rect(0.0, 0.0, 20.0, 10.0, [0.0, 0.0, 0.0, 0.5]);
+    assert_eq!(c.pixels[5 * 20 + 5], [0.5, 0.0, 0.0]);
+    let px = c.rgba8();
+    assert_eq!(px.len(), 20 * 10 * 4);
+    assert_eq!(&px[(5 * 20 + 5) * 4..(5 * 20 + 6) * 4], &[128, 0, 0, 255]);
+
+    let path = std::env::temp_dir().join("fluid_test_raster.png");
+    let path = path.to_str().unwrap();
+    output::write_png(path, &c).unwrap();
+    let (info, _) = png::Decoder::new(std::fs::File::open(path).unwrap()).read_info().unwrap();
+    assert_eq!((info.width, info.height), (20, 10));
+}
+
+#[test]
+fn test_y4m () {
+    use output::Sink;
+    let mut c = raster::Canvas::new(3, 3);
+    c.pixels[0] = [1.0, 1.0, 1.0];
+    let (y, u, v) = output::yuv420(&c);
+    assert_eq!((y.len(), u.len(), v.len()), (9, 4, 4));
+    assert_eq!((y[0], y[1]), (235, 16));
+    assert_eq!((u[3], v[3]), (128, 128));
+
+    let path = std::env::temp_dir().join("fluid_test.y4m");
+    let path = path.to_str().unwrap();
+    let sim = sim::Sim::new(10.0, 10.0, 0, clock::Clock::new(SIM_DT, SIM_RATE));
+    let mut r = raster::Renderer::new(3, 3);
+    r.canvas = c;
+    let mut out = output::Y4m::create(path, (60, 2)).unwrap();
+    out.frame(&sim, &r).unwrap();
+    out.frame(&sim, &r).unwrap();
+    r.canvas = raster::Canvas::new(2, 2);
+    assert!(out.frame(&sim, &r).is_err());
+    out.finish().unwrap();
+    let data = std::fs::read(path).unwrap();
+    let header = b"YUV4MPEG2 W3 H3 F60:2 Ip A1:1 C420jpeg\n";
+    assert!(data.starts_with(header));
+    assert_eq!(data.len(), header.len() + 2 * (6 + 9 + 4 + 4));
+}
+
+#[test]
+fn test_svg () {
+    let mut sim = sim::Sim::new(100.0, 80.0, 3, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.wells = vec![gravity::Well::new(vec::Vec::new_from(50.0, 40.0), -1.0)];
+    let doc = svg::render(&sim, 1.0, vec::Vec::new(), &svg::Options::default());
+    assert!(doc.starts_with("<svg"));
+    assert!(doc.ends_with("</svg>\n"));
+    assert_eq!(doc.matches("r=\"2.8\"").count(), 3);
+    assert!(doc.contains("repelling well"));
+    assert!(!doc.contains("id=\"velocities\""));
+    let doc = svg::render(&sim, 1.0, vec::Vec::new(), &svg::Options { arrows: true });
+    assert!(doc.contains("id=\"velocities\""));
+}
+
+#[test]
+fn test_term () {
+    assert!(term::Style::parse("dots").is_err());
+    let mut sim = sim::Sim::new(100.0, 100.0, 1, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.agents[0].pos = vec::Vec::new_from(1.0, 1.0);
+    sim.agents[0].color = [0.0, 0.5, 0.0];
+    let t = term::Terminal::new(term::Style::Blocks, (4, 3), 10.0);
+    let frame = t.render(&sim);
+    assert_eq!(frame.matches('▀').count(), 8);
+    assert!(frame.starts_with("\x1b[H\x1b[38;2;0;255;0m"));
+    let t = term::Terminal::new(term::Style::Braille, (4, 3), 10.0);
+    assert!(t.render(&sim).contains('⠁'));
+    assert!(cli::parse(["--headless", "--y4m", "-", "--term", "blocks"].iter().map(|a| a.to_string())).is_err());
+}
+
+#[test]
+fn test_dump () {
+    let sim = sim::Sim::new(100.0, 100.0, 2, clock::Clock::new(SIM_DT, SIM_RATE));
+    let dir = std::env::temp_dir();
+    let csv = dir.join("fluid_test_dump.csv");
+    dump::write_csv(csv.to_str().unwrap(), &sim.agents).unwrap();
+    let text = std::fs::read_to_string(csv).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "id,x,y,vx,vy,r,g,b,view_range,pos_w,vel_w,drag,s_in_range,s_vel");
+    assert!(lines.iter().all(|l| l.split(',').count() == 14));
+
+    let vtk = dir.join("fluid_test_dump.vtk");
+    dump::write_vtk(vtk.to_str().unwrap(), &sim).unwrap();
+    let text = std::fs::read_to_string(vtk).unwrap();
+    assert!(text.contains("POINTS 2 float\n"));
+    assert!(text.contains("VERTICES 2 4\n1 0\n1 1\n"));
+    assert_eq!(text.matches("LOOKUP_TABLE").count(), 7);
+}
+
+#[test]
+fn test_npy () {
+    use output::Sink;
+    assert_eq!(npy::header(&[12, 4000, 2]).len(), 128);
+    let sim = sim::Sim::new(100.0, 100.0, 3, clock::Clock::new(SIM_DT, SIM_RATE));
+    let dir = std::env::temp_dir().join("fluid_test_npy");
+    let dir = dir.to_str().unwrap();
+    let mut t = npy::Trajectory::new(dir, 9).unwrap();
+    t.frame(&sim, &raster::Renderer::new(1, 1)).unwrap();
+    t.frame(&sim, &raster::Renderer::new(1, 1)).unwrap();
+    let data = std::fs::read(format!("{}/color.npy", dir)).unwrap();
+    assert_eq!(&data[..8], b"\x93NUMPY\x01\x00");
+    assert!(String::from_utf8_lossy(&data[10..128]).contains("'shape': (2, 3, 3,)"));
+    assert_eq!(data.len(), 128 + 2 * 3 * 3 * 4);
+    assert_eq!(&data[128..132], &sim.agents[0].color[0].to_le_bytes());
+    let meta: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(format!("{}/trajectory.json", dir)).unwrap()).unwrap();
+    assert_eq!(meta["seed"], 9);
+    assert_eq!(meta["fields"]["pos"]["shape"], json!([2, 3, 2]));
+}
+
+#[test]
+fn test_metrics () {
+    let mut sim = sim::Sim::new(100.0, 100.0, 2, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.agents[0].vel = vec::Vec::new_from(3.0, 0.0);
+    sim.agents[0].color = [1.0, 0.0, 0.0];
+    sim.agents[1].vel = vec::Vec::new_from(0.0, -4.0);
+    sim.agents[1].color = [0.0, 0.2, 0.8];
+    sim.agents[0].s_in_range = 3;
+    let m = metrics::measure(&sim);
+    assert_eq!(m.kinetic_energy, 12.5);
+    assert_eq!(m.momentum, [3.0, -4.0]);
+    assert_eq!(m.color_count, [1, 0, 1]);
+    assert_eq!((m.max_neighbours, m.mean_neighbours), (3.0, 1.5));
+
+    // A single writer can have stdout
+    let args = |s: &str| cli::parse(s.split(' ').map(|a| a.to_string()));
+    assert!(args("--headless --metrics - --y4m out.y4m").is_ok());
+    assert!(args("--headless --metrics - --y4m -").is_err());
+    assert!(args("--headless --metrics - --term braille").is_err());
+    assert!(args("--headless --y4m - --term blocks").is_err());
+}
+
+#[test]
+fn test_graph () {
+    assert!(graph::check(&["speed".to_string(), "energy".to_string()]).is_ok());
+    assert!(graph::check(&["pressure".to_string()]).is_err());
+    let mut sim = sim::Sim::new(10.0, 10.0, 1, clock::Clock::new(SIM_DT, SIM_RATE));
+    let mut g = graph::Graph::new();
+    for _ in 0..35 {
+        sim.clock.tick();
+        g.step(&sim);
+    }
+    assert_eq!(g.len(), 3);
+    // Going back in time drops the samples after it
+    sim.clock.steps = 15;
+    g.step(&sim);
+    assert_eq!(g.len(), 1);
+}
+
+#[test]
+fn test_colormap () {
+    use colormap::{Map, Mode};
+    assert_eq!(Map::Viridis.sample(0.0), [0x44 as f32 / 255.0, 0x01 as f32 / 255.0, 0x54 as f32 / 255.0]);
+    assert_eq!(Map::Magma.sample(2.0), Map::Magma.sample(1.0));
+    assert_eq!(Mode::Weirdness.next(), Mode::Color);
+    let values: Vec<f32> = (0..=100).map(|v| v as f32).collect();
+    assert_eq!(colormap::auto_range(&values), (2.0, 98.0));
+
+    let mut sim = sim::Sim::new(100.0, 100.0, 2, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.agents[0].s_vel = 1.0;
+    let mut c = colormap::Coloring { mode: Mode::Speed, range: Some((0.0, 1.0)), ..Default::default() };
+    let colors = c.colors(&sim, &sim.neighbours()).unwrap();
+    assert_eq!(colors[0], [0xfd as f32 / 255.0, 0xe7 as f32 / 255.0, 0x25 as f32 / 255.0, 1.0]);
+    c.mode = Mode::Color;
+    assert!(c.colors(&sim, &sim.neighbours()).is_none());
+
+    // Vorticity looks at where the neighbours are now, not at the last step
+    sim.agents[0].pos = vec::Vec::new_from(50.0, 50.0);
+    sim.agents[1].pos = vec::Vec::new_from(55.0, 50.0);
+    sim.agents[0].vel = vec::Vec::new();
+    sim.agents[1].vel = vec::Vec::new_from(0.0, -1.0);
+    let v = colormap::values(&sim, &sim.neighbours(), Mode::Vorticity).unwrap();
+    assert!((v[0] - 0.2).abs() < 1e-6);
+
+    let table: toml::value::Table = toml::from_str("[render]\ncolor_mode = \"vorticity\"\ncolor_range = []").unwrap();
+    let r = config::Config::parse(&table).unwrap().render.unwrap();
+    let c = r.coloring(&colormap::Coloring { range: Some((0.0, 1.0)), ..Default::default() }).unwrap();
+    assert_eq!((c.mode, c.range), (Mode::Vorticity, None));
+    let table: toml::value::Table = toml::from_str("[render]\ncolor_range = [2, 1]").unwrap();
+    assert!(config::Config::parse(&table).unwrap().render.unwrap().coloring(&c).is_err());
+}
+
+#[test]
+fn test_flow () {
+    use flow::Grid;
+    // Agents all going right
+    let mut sim = sim::Sim::new(100.0, 100.0, 400, clock::Clock::new(SIM_DT, SIM_RATE));
+    for (i, a) in sim.agents.iter_mut().enumerate() {
+        a.pos = vec::Vec::new_from((i % 20) as f32 * 5.0, (i / 20) as f32 * 5.0);
+        a.vel = vec::Vec::new_from(2.0, 0.0);
+    }
+    // Moved since the last step, as after a brush stroke
+    let grid = Grid::resample(&sim, &sim.neighbours(), 10.0);
+    assert_eq!((grid.nx, grid.ny), (10, 10));
+    assert!(grid.vel.iter().all(|v| (v.x - 2.0).abs() < 1e-4 && v.y.abs() < 1e-4));
+    let v = grid.at(3.0, 97.0);
+    assert!((v.x - 2.0).abs() < 1e-4);
+
+    // A shaft and two head strokes per node, one cell long
+    let arrows = flow::arrows(&grid, 1.0);
+    assert_eq!(arrows.len(), 300);
+    assert_eq!(arrows[0], ((5.0, 5.0), (15.0, 5.0)));
+    let line = grid.trace(50.0, 50.0, 5.0, 4);
+    assert_eq!(line.len(), 5);
+    assert!(line.iter().all(|p| (p.1 - 50.0).abs() < 1e-4));
+    assert!((line[4].0 - 70.0).abs() < 1e-4);
+    let lines = flow::streamlines(&grid, 5, 2.0);
+    assert_eq!(lines.len(), 4);
+    assert!(flow::lic(&grid, 25, 25, 4.0, 2.0).iter().all(|g| (0.0..=1.0).contains(g)));
+
+    let table: toml::value::Table = toml::from_str("[render]\nflow = \"lic\"\nflow_cell = 8").unwrap();
+    let f = config::Config::parse(&table).unwrap().render.unwrap().flow(&flow::Flow::default()).unwrap();
+    assert_eq!((f.overlay, f.cell, f.length), (flow::Overlay::Lic, 8.0, 1.0));
+    let table: toml::value::Table = toml::from_str("[render]\nflow_length = 0").unwrap();
+    assert!(config::Config::parse(&table).unwrap().render.unwrap().flow(&f).is_err());
+}
+
+#[test]
+fn test_surface () {
+    // A lone agent is a disc where its metaball is above iso
+    let mut sim = sim::Sim::new(100.0, 100.0, 1, clock::Clock::new(SIM_DT, SIM_RATE));
+    sim.agents[0].pos = vec::Vec::new_from(50.0, 50.0);
+    sim.agents[0].color = [1.0, 0.0, 0.0];
+    let s = surface::Surface { enabled: true, cell: 2.0, ..Default::default() };
+    let f = s.field(&sim, &sim.neighbours());
+    assert_eq!((f.nx, f.ny), (50, 50));
+    let c = surface::contours(&f, s.iso);
+    assert_eq!(c.len(), 1);
+    assert!(c[0].closed);
+    let r = s.radius * (1.0 - s.iso.sqrt()).sqrt();
+    let disc = std::f32::consts::PI * r * r;
+    assert!((c[0].area.unwrap() - disc).abs() < disc * 0.05);
+    assert!(c[0].points.iter().all(|p| ((p[0] - 50.0).hypot(p[1] - 50.0) - r).abs() < 0.5));
+    assert!(!surface::cells(&f, s.iso).is_empty());
+
+    let mut canvas = raster::Canvas::new(100, 100);
+    surface::paint(&mut canvas, &f, s.iso, 1.0, 1.0);
+    assert_eq!(canvas.pixels[50 * 100 + 50], [1.0, 0.0, 0.0]);
+    assert_eq!(canvas.pixels[50 * 100 + 60], [0.0, 0.0, 0.0]);
+
+    let table: toml::value::Table = toml::from_str("[render]\nsurface = true\nsurface_iso = 0").unwrap();
+    assert!(config::Config::parse(&table).unwrap().render.unwrap().surface(&s).is_err());
+}
+
 #[test]
 fn test_latex () {
     let res = 10.0;
@@ -542,15 +1763,15 @@ fn test_latex () {
     let h = 100.0;
     let mut latex = latex::Latex2D::new(res, w, h);
     latex.add((99.0, 0.0), 1);
-    assert!(latex.get((0.0, 0.0), 2.0) == vec![1]);
-    assert!(latex.get((0.0, 0.0), 10.0) == vec![1]);
-    assert!(latex.get((0.0, 0.0), 20.0) == vec![1]);
-    assert!(latex.get((0.0, 0.0), 1000.0) == vec![1]);
+    assert!(latex.get((0.0, 0.0), 2.0) == vec![&1]);
+    assert!(latex.get((0.0, 0.0), 10.0) == vec![&1]);
+    assert!(latex.get((0.0, 0.0), 20.0) == vec![&1]);
+    assert!(latex.get((0.0, 0.0), 1000.0) == vec![&1]);
 
     let mut latex = latex::Latex2D::new(res, w, h);
     latex.add((99.0, 99.0), 1);
     latex.add((51.0, 0.0), 2);
     latex.add((50.0, 0.0), 3);
-    assert!(latex.get((0.0, 0.0), 2.0) == vec![1]);
-    assert!(latex.get((50.0, 0.0), 10.0) == vec![2, 3]);
+    assert!(latex.get((0.0, 0.0), 2.0) == vec![&1]);
+    assert!(latex.get((50.0, 0.0), 10.0) == vec![&2, &3]);
 }
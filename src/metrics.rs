@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::sim::{self, Sim};
+use crate::utils;
+
+// Steps between two samples when not given
+pub const EVERY: u64 = 60;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Metrics {
+    pub step: u64,
+    pub time: f64,
+    pub agents: usize,
+    pub max_speed: f32,
+    pub mean_speed: f32,
+    pub max_neighbours: f32,
+    pub mean_neighbours: f32,
+    // With a unit mass per agent
+    pub kinetic_energy: f32,
+    pub momentum: [f32; 2],
+    // Colour totals normalised to 1, as shown by the stats bar
+    pub color_share: [f32; 3],
+    // Agents whose strongest channel is red, green or blue
+    pub color_count: [usize; 3],
+    // Maxima averaged over the last ST_LEN samples
+    pub max_speed_avg: f32,
+    pub max_neighbours_avg: f32,
+}
+
+pub fn measure(sim: &Sim) -> Metrics {
+    let (max_speed, color_share) = sim.stats();
+    let n = sim.agents.len().max(1) as f32;
+    let (speed, neighbours, max_neighbours, kinetic, px, py) = sim.agents.par_iter()
+        .fold(|| (0.0, 0.0, 0.0, 0.0, 0.0, 0.0), |v: (f32, f32, f32, f32, f32, f32), x| (
+            v.0 + x.s_vel,
+            v.1 + x.s_in_range as f32,
+            v.2.max(x.s_in_range as f32),
+            v.3 + 0.5 * (x.vel.x * x.vel.x + x.vel.y * x.vel.y),
+            v.4 + x.vel.x,
+            v.5 + x.vel.y,
+        ))
+        .reduce(|| (0.0, 0.0, 0.0, 0.0, 0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1, a.2.max(b.2), a.3 + b.3, a.4 + b.4, a.5 + b.5));
+    let mut color_count = [0; 3];
+    for x in sim.agents.iter() {
+        let c = x.color;
+        let i = if c[0] >= c[1] && c[0] >= c[2] { 0 } else if c[1] >= c[2] { 1 } else { 2 };
+        color_count[i] += 1;
+    }
+    Metrics {
+        step: sim.clock.steps,
+        time: sim.clock.time,
+        agents: sim.agents.len(),
+        max_speed,
+        mean_speed: speed / n,
+        max_neighbours,
+        mean_neighbours: neighbours / n,
+        kinetic_energy: kinetic,
+        momentum: [px, py],
+        color_share,
+        color_count,
+        max_speed_avg: max_speed,
+        max_neighbours_avg: max_neighbours,
+    }
+}
+
+// Writes a Metrics object per line every `every` steps
+pub struct Stream {
+    pub every: u64,
+    out: Box<dyn Write>,
+    avg_speed: Vec<f32>,
+    avg_neighbours: Vec<f32>,
+}
+
+impl Stream {
+    // `-` writes to stdout, the logs then have to go elsewhere
+    pub fn create(path: &str, every: u64) -> Result<Stream, String> {
+        let out: Box<dyn Write> = if path == "-" {
+            utils::log_to_stderr();
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?))
+        };
+        Ok(Stream { every, out, avg_speed: vec![], avg_neighbours: vec![] })
+    }
+
+    // Call after every step, returns what was written if it was time to
+    pub fn step(&mut self, sim: &Sim) -> Option<Metrics> {
+        if !sim.clock.steps.is_multiple_of(self.every) { return None; }
+        let mut m = measure(sim);
+        for (avg, v) in [(&mut self.avg_speed, &mut m.max_speed_avg), (&mut self.avg_neighbours, &mut m.max_neighbours_avg)] {
+            avg.push(*v);
+            if avg.len() > sim::ST_LEN { avg.remove(0); }
+            *v = utils::avg(avg);
+        }
+        if let Ok(line) = serde_json::to_string(&m) {
+            // Like the logs, a failing disk shouldn't stop the simulation
+            let _ = writeln!(self.out, "{}", line).and_then(|_| self.out.flush());
+        }
+        Some(m)
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use serde_json::json;
+use crate::ag::Agent;
+use crate::output::Sink;
+use crate::raster::Renderer;
+use crate::sim::Sim;
+
+// The header is padded to this size, so it can be rewritten in place with the
+// new frame count whatever the number of digits
+const HEADER_LEN: usize = 128;
+
+type Field = (&'static str, usize, fn(&Agent) -> [f32; 3]);
+
+// File name, values per agent and how to get them
+const FIELDS: &[Field] = &[
+    ("pos", 2, |a| [a.pos.x, a.pos.y, 0.0]),
+    ("vel", 2, |a| [a.vel.x, a.vel.y, 0.0]),
+    ("color", 3, |a| a.color),
+];
+
+// NumPy format 1.0 header of a little endian float32 array
+pub fn header(shape: &[usize]) -> Vec<u8> {
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let mut dict = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({},), }}", dims.join(", "));
+    while dict.len() < HEADER_LEN - 11 {
+        dict.push(' ');
+    }
+    dict.push('\n');
+    let mut h = b"\x93NUMPY\x01\x00".to_vec();
+    h.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    h.extend_from_slice(dict.as_bytes());
+    h
+}
+
+// One .npy per field with the frames along the first axis, e.g. pos.npy is
+// (frames, agents, 2), and trajectory.json with what is needed to read them
+pub struct Trajectory {
+    dir: String,
+    seed: u64,
+    files: Vec<BufWriter<File>>,
+    agents: usize,
+    steps: Vec<u64>,
+}
+
+impl Trajectory {
+    pub fn new(dir: &str, seed: u64) -> Result<Trajectory, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        let mut files = vec![];
+        for (name, ..) in FIELDS.iter() {
+            let path = format!("{}/{}.npy", dir, name);
+            files.push(BufWriter::new(File::create(&path).map_err(|e| format!("{}: {}", path, e))?));
+        }
+        Ok(Trajectory { dir: dir.to_string(), seed, files, agents: 0, steps: vec![] })
+    }
+
+    fn sidecar(&self, sim: &Sim) -> Result<(), String> {
+        let path = format!("{}/trajectory.json", self.dir);
+        let fields: serde_json::Map<_, _> = FIELDS.iter()
+            .map(|(name, width, _)| (name.to_string(), json!({
+                "file": format!("{}.npy", name),
+                "shape": [self.steps.len(), self.agents, *width],
+            })))
+            .collect();
+        let doc = json!({
+            "w": sim.w,
+            "h": sim.h,
+            "seed": self.seed,
+            "agents": self.agents,
+            "dt": sim.clock.dt,
+            "latex_div": sim.latex_div,
+            "frames": self.steps.len(),
+            "steps": self.steps,
+            "fields": fields,
+        });
+        std::fs::write(&path, format!("{:#}\n", doc)).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+impl Sink for Trajectory {
+    fn needs_canvas(&self) -> bool {
+        false
+    }
+
+    fn frame(&mut self, sim: &Sim, _renderer: &Renderer) -> Result<(), String> {
+        if self.steps.is_empty() {
+            self.agents = sim.agents.len();
+        } else if sim.agents.len() != self.agents {
+            return Err("the number of agents changed, npy frames need a fixed one".to_string());
+        }
+        let (frames, agents) = (self.steps.len() + 1, self.agents);
+        for (f, (name, width, get)) in self.files.iter_mut().zip(FIELDS.iter()) {
+            let mut data = Vec::with_capacity(agents * width * 4);
+            for a in sim.agents.iter() {
+                get(a)[..*width].iter().for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+            }
+            (|| {
+                f.seek(SeekFrom::Start(0))?;
+                f.write_all(&header(&[frames, agents, *width]))?;
+                f.seek(SeekFrom::End(0))?;
+                f.write_all(&data)?;
+                f.flush()
+            })().map_err(|e| format!("{}.npy: {}", name, e))?;
+        }
+        self.steps.push(sim.clock.steps);
+        self.sidecar(sim)
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use crate::raster::{Canvas, Renderer};
+use crate::sim::Sim;
+use crate::svg;
+use crate::vec;
+
+// Destination of the frames rendered by a headless run
+pub trait Sink {
+    // False for the outputs that only look at the simulation, the frame is
+    // then not rasterised for them
+    fn needs_canvas(&self) -> bool {
+        true
+    }
+
+    fn frame(&mut self, sim: &Sim, renderer: &Renderer) -> Result<(), String>;
+
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// frame_00000.png, frame_00001.png, ... in `dir`
+pub struct PngSequence {
+    dir: String,
+    count: usize,
+}
+
+impl PngSequence {
+    pub fn new(dir: &str) -> Result<PngSequence, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        Ok(PngSequence { dir: dir.to_string(), count: 0 })
+    }
+}
+
+pub fn write_png(path: &str, canvas: &Canvas) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), canvas.w as u32, canvas.h as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&canvas.rgba8()).map_err(|e| e.to_string())
+}
+
+impl Sink for PngSequence {
+    fn frame(&mut self, _sim: &Sim, renderer: &Renderer) -> Result<(), String> {
+        let path = format!("{}/frame_{:05}.png", self.dir, self.count);
+        self.count += 1;
+        write_png(&path, &renderer.canvas)
+    }
+}
+
+// frame_00000.svg, ... in `dir`
+pub struct SvgSequence {
+    dir: String,
+    count: usize,
+    opts: svg::Options,
+}
+
+impl SvgSequence {
+    pub fn new(dir: &str, opts: svg::Options) -> Result<SvgSequence, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        Ok(SvgSequence { dir: dir.to_string(), count: 0, opts })
+    }
+}
+
+impl Sink for SvgSequence {
+    fn needs_canvas(&self) -> bool {
+        false
+    }
+
+    fn frame(&mut self, sim: &Sim, renderer: &Renderer) -> Result<(), String> {
+        let path = format!("{}/frame_{:05}.svg", self.dir, self.count);
+        self.count += 1;
+        svg::save(&path, &svg::render(sim, renderer.max_speed, vec::Vec::new(), &self.opts))
+    }
+}
+
+// YUV4MPEG2 stream, 4:2:0 with BT.601 studio range, for piping into an
+// encoder. `-` writes to stdout.
+pub struct Y4m {
+    out: Box<dyn Write>,
+    // Frames per second as a fraction
+    rate: (u32, u32),
+    size: Option<(usize, usize)>,
+}
+
+impl Y4m {
+    pub fn create(path: &str, rate: (u32, u32)) -> Result<Y4m, String> {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?))
+        };
+        Ok(Y4m { out, rate, size: None })
+    }
+}
+
+pub fn yuv420(canvas: &Canvas) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let (w, h) = (canvas.w, canvas.h);
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    let y = canvas.pixels.iter()
+        .map(|p| (16.0 + 65.481 * p[0] + 128.553 * p[1] + 24.966 * p[2]).round() as u8)
+        .collect();
+    let mut u = Vec::with_capacity(cw * ch);
+    let mut v = Vec::with_capacity(cw * ch);
+    for cy in 0..ch {
+        for cx in 0..cw {
+            // Average of the pixels under this chroma sample
+            let mut rgb = [0.0; 3];
+            let mut n = 0.0;
+            for py in cy * 2..(cy * 2 + 2).min(h) {
+                for px in cx * 2..(cx * 2 + 2).min(w) {
+                    let p = canvas.pixels[py * w + px];
+                    (0..3).for_each(|i| rgb[i] += p[i].clamp(0.0, 1.0));
+                    n += 1.0;
+                }
+            }
+            let [r, g, b] = [rgb[0] / n, rgb[1] / n, rgb[2] / n];
+            u.push((128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8);
+            v.push((128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8);
+        }
+    }
+    (y, u, v)
+}
+
+impl Sink for Y4m {
+    fn frame(&mut self, _sim: &Sim, renderer: &Renderer) -> Result<(), String> {
+        let canvas = &renderer.canvas;
+        let size = (canvas.w, canvas.h);
+        match self.size {
+            None => {
+                writeln!(self.out, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg", size.0, size.1, self.rate.0, self.rate.1)
+                    .map_err(|e| e.to_string())?;
+                self.size = Some(size);
+            },
+            Some(s) if s != size => return Err("y4m frames must all have the same size".to_string()),
+            _ => (),
+        }
+        let (y, u, v) = yuv420(canvas);
+        self.out.write_all(b"FRAME\n")
+            .and_then(|_| self.out.write_all(&y))
+            .and_then(|_| self.out.write_all(&u))
+            .and_then(|_| self.out.write_all(&v))
+            .map_err(|e| e.to_string())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,112 @@
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::nalgebra::Point2;
+use crate::params::Params;
+
+const MARGIN: f32 = 10.0;
+const TOP: f32 = 40.0;
+const ROW: f32 = 20.0;
+const LABEL: f32 = 110.0;
+const TRACK: f32 = 140.0;
+const WIDTH: f32 = LABEL + TRACK + 80.0;
+// Fraction of a slider range changed per wheel notch
+pub const WHEEL_STEP: f32 = 0.01;
+
+// Sliders for `Params` in the top right corner. Dragging on a track sets the
+// value, the wheel over a row nudges it for fine tuning.
+pub struct Panel {
+    pub enabled: bool,
+    dragging: Option<usize>,
+}
+
+impl Panel {
+    pub fn new() -> Panel {
+        Panel {
+            enabled: false,
+            dragging: None,
+        }
+    }
+
+    fn left(w: f32) -> f32 {
+        w - WIDTH - MARGIN
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.dragging.is_some()
+    }
+
+    // Whether the panel is shown under `(x, y)`, clicks there don't reach the brush
+    pub fn contains(&self, params: &mut Params, w: f32, x: f32, y: f32) -> bool {
+        let rows = params.sliders().len() as f32;
+        self.enabled && x >= Panel::left(w) && x < Panel::left(w) + WIDTH && y >= TOP && y < TOP + rows * ROW
+    }
+
+    fn row(&self, params: &mut Params, w: f32, x: f32, y: f32) -> Option<usize> {
+        if !self.contains(params, w, x, y) { return None; }
+        Some(((y - TOP) / ROW) as usize)
+    }
+
+    fn set(params: &mut Params, i: usize, w: f32, x: f32) -> Option<&'static str> {
+        let mut sliders = params.sliders();
+        let (name, min, max, value) = sliders.get_mut(i)?;
+        let t = ((x - Panel::left(w) - LABEL) / TRACK).clamp(0.0, 1.0);
+        **value = *min + t * (*max - *min);
+        Some(*name)
+    }
+
+    // Returns the name of the parameter that changed, if any
+    pub fn press(&mut self, params: &mut Params, w: f32, x: f32, y: f32) -> Option<&'static str> {
+        let i = self.row(params, w, x, y)?;
+        let track = Panel::left(w) + LABEL;
+        if x < track || x > track + TRACK { return None; }
+        self.dragging = Some(i);
+        Panel::set(params, i, w, x)
+    }
+
+    pub fn drag(&mut self, params: &mut Params, w: f32, x: f32) -> Option<&'static str> {
+        Panel::set(params, self.dragging?, w, x)
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = None;
+    }
+
+    pub fn scroll(&self, params: &mut Params, w: f32, x: f32, y: f32, amount: f32) -> Option<&'static str> {
+        let i = self.row(params, w, x, y)?;
+        let name = params.sliders().get(i)?.0;
+        params.nudge(name, amount * WHEEL_STEP)
+    }
+
+    pub fn draw(&self, ctx: &mut Context, params: &Params) -> GameResult<()> {
+        if !self.enabled { return Ok(()); }
+        let (w, _) = graphics::drawable_size(ctx);
+        let x0 = Panel::left(w);
+        let mut params = params.clone();
+        let sliders = params.sliders();
+
+        let mut mb = graphics::MeshBuilder::new();
+        mb.rectangle(graphics::DrawMode::fill(),
+            graphics::Rect::new(x0 - 5.0, TOP - 5.0, WIDTH + 10.0, sliders.len() as f32 * ROW + 10.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.6));
+        let mut lines = vec![];
+        for (i, (name, min, max, value)) in sliders.iter().enumerate() {
+            let y = TOP + i as f32 * ROW;
+            let t = ((**value - min) / (max - min)).clamp(0.0, 1.0);
+            let col = if self.dragging == Some(i) { 1.0 } else { 0.7 };
+            mb.rectangle(graphics::DrawMode::stroke(1.0),
+                graphics::Rect::new(x0 + LABEL, y + 4.0, TRACK, ROW - 8.0),
+                graphics::Color::new(col, col, col, 0.8));
+            mb.rectangle(graphics::DrawMode::fill(),
+                graphics::Rect::new(x0 + LABEL, y + 4.0, TRACK * t, ROW - 8.0),
+                graphics::Color::new(0.3, 0.6, 1.0, 0.8));
+            lines.push((format!("{:<14}", name), format!("{:.4}", value), y));
+        }
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::new())?;
+        for (name, value, y) in lines {
+            graphics::draw(ctx, &graphics::Text::new(name), graphics::DrawParam::new().dest(Point2::new(x0, y + 3.0)))?;
+            graphics::draw(ctx, &graphics::Text::new(value), graphics::DrawParam::new().dest(Point2::new(x0 + LABEL + TRACK + 8.0, y + 3.0)))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Serialize, Deserialize};
+
+// Coefficients of the agent model, tunable while the simulation runs. An agent
+// derives its weights from its colour, e.g. vel_w = vel_w_base + red * vel_w_red.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Params {
+    pub vel_w_base: f32,
+    pub vel_w_red: f32,
+    pub pos_w_base: f32,
+    pub pos_w_green: f32,
+    pub view_base: f32,
+    pub view_blue: f32,
+    pub view_scale: f32,
+    pub drag_red: f32,
+    pub drag_blue: f32,
+    pub weirdness: f32,
+    // Scale of the flocking acceleration
+    pub acc_scale: f32,
+    pub gravity_f: f32,
+    pub brush_size: f32,
+    // Opacity of the background drawn over the previous frame, lower values
+    // leave longer trails
+    pub trail_alpha: f32,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            vel_w_base: 0.1,
+            vel_w_red: 2.0,
+            pos_w_base: 0.1,
+            pos_w_green: 0.8,
+            view_base: 10.0,
+            view_blue: 40.0,
+            view_scale: 0.5,
+            drag_red: 0.001,
+            drag_blue: 0.01,
+            weirdness: 1.0,
+            acc_scale: 0.1,
+            gravity_f: 1.0,
+            brush_size: 50.0,
+            trail_alpha: 0.97,
+        }
+    }
+}
+
+impl Params {
+    // (name, min, max, value) of every tunable parameter
+    pub fn sliders(&mut self) -> Vec<(&'static str, f32, f32, &mut f32)> {
+        vec![
+            ("vel_w_base", 0.0, 2.0, &mut self.vel_w_base),
+            ("vel_w_red", 0.0, 5.0, &mut self.vel_w_red),
+            ("pos_w_base", 0.0, 2.0, &mut self.pos_w_base),
+            ("pos_w_green", 0.0, 2.0, &mut self.pos_w_green),
+            ("view_base", 1.0, 50.0, &mut self.view_base),
+            ("view_blue", 0.0, 100.0, &mut self.view_blue),
+            ("view_scale", 0.1, 2.0, &mut self.view_scale),
+            ("drag_red", 0.0, 0.05, &mut self.drag_red),
+            ("drag_blue", 0.0, 0.05, &mut self.drag_blue),
+            ("weirdness", 0.0, 5.0, &mut self.weirdness),
+            ("acc_scale", 0.0, 0.5, &mut self.acc_scale),
+            ("gravity_f", 0.0, 9.0, &mut self.gravity_f),
+            ("brush_size", 5.0, 300.0, &mut self.brush_size),
+            ("trail_alpha", 0.05, 1.0, &mut self.trail_alpha),
+        ]
+    }
+
+    // Move `name` by `amount` times its slider range, staying within it
+    pub fn nudge(&mut self, name: &str, amount: f32) -> Option<&'static str> {
+        let mut sliders = self.sliders();
+        let (name, min, max, value) = sliders.iter_mut().find(|s| s.0 == name)?;
+        **value = (**value + amount * (*max - *min)).clamp(*min, *max);
+        Some(*name)
+    }
+
+    // Values outside the slider ranges are rejected
+    pub fn validate(&mut self) -> Result<(), String> {
+        for (name, min, max, v) in self.sliders() {
+            if !v.is_finite() || *v < min || *v > max {
+                return Err(format!("{} = {} is outside [{}, {}]", name, v, min, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn save(path: &str, params: &Params) -> Result<(), String> {
+    let text = toml::to_string_pretty(params).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+pub fn load(path: &str) -> Result<Params, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut p: Params = toml::from_str(&text).map_err(|e| e.to_string())?;
+    p.validate()?;
+    Ok(p)
+}
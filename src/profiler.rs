@@ -0,0 +1,147 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use serde_json::json;
+
+// Frames kept for the rolling min/mean/max
+const WINDOW: usize = 120;
+// Scopes kept for the Chrome trace export
+const MAX_EVENTS: usize = 200_000;
+
+struct Event {
+    path: String,
+    tid: usize,
+    start: f64,
+    dur: f64,
+}
+
+#[derive(Default)]
+struct Series {
+    // Total time spent per frame, over all calls and threads
+    frames: VecDeque<f64>,
+    current: f64,
+    calls: usize,
+    frame_calls: VecDeque<usize>,
+}
+
+struct Profiler {
+    start: Instant,
+    events: VecDeque<Event>,
+    series: HashMap<String, Series>,
+}
+
+pub struct Stat {
+    pub path: String,
+    pub name: String,
+    pub depth: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub calls: f64,
+}
+
+static PROFILER: OnceLock<Mutex<Profiler>> = OnceLock::new();
+static NEXT_TID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static STACK: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    static TID: usize = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+}
+
+fn profiler() -> &'static Mutex<Profiler> {
+    PROFILER.get_or_init(|| Mutex::new(Profiler {
+        start: Instant::now(),
+        events: VecDeque::new(),
+        series: HashMap::new(),
+    }))
+}
+
+// Times the enclosing block until it is dropped. Scopes opened while another
+// one is alive on the same thread are nested under it, e.g. "update/latex".
+pub struct Scope {
+    path: String,
+    start: Instant,
+}
+
+pub fn scope(name: &'static str) -> Scope {
+    let path = STACK.with(|s| {
+        let mut s = s.borrow_mut();
+        s.push(name);
+        s.join("/")
+    });
+    Scope { path, start: Instant::now() }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let dur = self.start.elapsed().as_secs_f64();
+        STACK.with(|s| s.borrow_mut().pop());
+        let tid = TID.with(|t| *t);
+        let mut p = profiler().lock().unwrap();
+        let start = self.start.duration_since(p.start).as_secs_f64();
+        if p.events.len() >= MAX_EVENTS {
+            p.events.pop_front();
+        }
+        p.events.push_back(Event { path: self.path.clone(), tid, start, dur });
+        let series = p.series.entry(self.path.clone()).or_default();
+        series.current += dur;
+        series.calls += 1;
+    }
+}
+
+// Closes the current frame, scopes that didn't run in it keep their history
+pub fn frame() {
+    let mut p = profiler().lock().unwrap();
+    for series in p.series.values_mut() {
+        if series.calls == 0 {
+            continue;
+        }
+        series.frames.push_back(series.current);
+        series.frame_calls.push_back(series.calls);
+        if series.frames.len() > WINDOW {
+            series.frames.pop_front();
+            series.frame_calls.pop_front();
+        }
+        series.current = 0.0;
+        series.calls = 0;
+    }
+}
+
+// Rolling per frame statistics in seconds, parents come before children
+pub fn stats() -> Vec<Stat> {
+    let p = profiler().lock().unwrap();
+    let mut v: Vec<Stat> = p.series.iter().filter(|(_, s)| !s.frames.is_empty()).map(|(path, s)| {
+        let n = s.frames.len() as f64;
+        Stat {
+            path: path.clone(),
+            name: path.rsplit('/').next().unwrap_or("").to_string(),
+            depth: path.matches('/').count(),
+            min: s.frames.iter().cloned().fold(f64::MAX, f64::min),
+            mean: s.frames.iter().sum::<f64>() / n,
+            max: s.frames.iter().cloned().fold(0.0, f64::max),
+            calls: s.frame_calls.iter().sum::<usize>() as f64 / n,
+        }
+    }).collect();
+    v.sort_by(|a, b| a.path.cmp(&b.path));
+    v
+}
+
+// Writes the recorded scopes in the Chrome trace event format, to be opened
+// with chrome://tracing or https://ui.perfetto.dev
+pub fn export_chrome(path: &str) -> std::io::Result<usize> {
+    let p = profiler().lock().unwrap();
+    let events: Vec<serde_json::Value> = p.events.iter().map(|e| json!({
+        "name": e.path.rsplit('/').next().unwrap_or(""),
+        "cat": e.path,
+        "ph": "X",
+        "ts": e.start * 1e6,
+        "dur": e.dur * 1e6,
+        "pid": 1,
+        "tid": e.tid,
+    })).collect();
+    let trace = json!({ "traceEvents": events, "displayTimeUnit": "ms" });
+    std::fs::write(path, trace.to_string())?;
+    Ok(p.events.len())
+}
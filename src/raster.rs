@@ -0,0 +1,122 @@
+use crate::colormap::Coloring;
+use crate::sim::{self, Sim};
+use crate::surface::{self, Surface};
+use crate::utils;
+
+// RGB image on the CPU, for when there is no window to draw on. Colours are
+// blended over an opaque background, so no alpha channel is stored.
+pub struct Canvas {
+    pub w: usize,
+    pub h: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl Canvas {
+    pub fn new(w: usize, h: usize) -> Canvas {
+        Canvas { w, h, pixels: vec![[0.0; 3]; w * h] }
+    }
+
+    // `col` is a straight alpha colour, `cover` the part of the pixel covered
+    pub fn blend(&mut self, x: usize, y: usize, col: [f32; 4], cover: f32) {
+        let a = (col[3] * cover).clamp(0.0, 1.0);
+        let p = &mut self.pixels[y * self.w + x];
+        for i in 0..3 {
+            p[i] += (col[i] - p[i]) * a;
+        }
+    }
+
+    pub fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, col: [f32; 4]) {
+        let x0 = x.max(0.0).round() as usize;
+        let y0 = y.max(0.0).round() as usize;
+        let x1 = ((x + w).round().max(0.0) as usize).min(self.w);
+        let y1 = ((y + h).round().max(0.0) as usize).min(self.h);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                self.blend(px, py, col, 1.0);
+            }
+        }
+    }
+
+    // Filled circle with a one pixel soft edge
+    pub fn circle(&mut self, cx: f32, cy: f32, r: f32, col: [f32; 4]) {
+        let x0 = (cx - r - 1.0).floor().max(0.0) as usize;
+        let y0 = (cy - r - 1.0).floor().max(0.0) as usize;
+        let x1 = ((cx + r + 1.0).ceil().max(0.0) as usize).min(self.w);
+        let y1 = ((cy + r + 1.0).ceil().max(0.0) as usize).min(self.h);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let dx = px as f32 + 0.5 - cx;
+                let dy = py as f32 + 0.5 - cy;
+                let cover = (r + 0.5 - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+                if cover > 0.0 {
+                    self.blend(px, py, col, cover);
+                }
+            }
+        }
+    }
+
+    pub fn rgba8(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(self.w * self.h * 4);
+        for p in self.pixels.iter() {
+            v.extend(p.iter().map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+            v.push(255);
+        }
+        v
+    }
+}
+
+// Draws the simulation like the window does: the translucent background that
+// leaves trails, the agents and the colour share bar. The canvas can have a
+// different size than the world, everything is scaled to fit.
+pub struct Renderer {
+    pub canvas: Canvas,
+    // Speed drawn at full opacity in the last frame
+    pub max_speed: f32,
+    pub coloring: Coloring,
+    pub surface: Surface,
+    avg_stats_vel: Vec<f32>,
+}
+
+impl Renderer {
+    pub fn new(w: usize, h: usize) -> Renderer {
+        Renderer { canvas: Canvas::new(w, h), max_speed: 0.0, coloring: Coloring::default(), surface: Surface::default(), avg_stats_vel: vec![] }
+    }
+
+    // Follow the speeds without drawing, returns the colour share
+    pub fn track(&mut self, sim: &Sim) -> [f32; 3] {
+        let (max_speed, col) = sim.stats();
+        self.avg_stats_vel.push(max_speed);
+        if self.avg_stats_vel.len() > sim::ST_LEN { self.avg_stats_vel.remove(0); }
+        self.max_speed = utils::avg(&self.avg_stats_vel);
+        col
+    }
+
+    pub fn draw(&mut self, sim: &Sim) {
+        let col = self.track(sim);
+        let max_speed = self.max_speed;
+        let c = &mut self.canvas;
+        let (sx, sy) = (c.w as f32 / sim.w, c.h as f32 / sim.h);
+        c.rect(0.0, 0.0, c.w as f32, c.h as f32, [0.0, 0.0, 0.0, sim.params.trail_alpha]);
+
+        let latex = sim.neighbours();
+        if self.surface.enabled {
+            surface::paint(c, &self.surface.field(sim, &latex), self.surface.iso, sx, sy);
+        } else {
+            let colors = self.coloring.colors(sim, &latex);
+            for (i, x) in sim.agents.iter().enumerate() {
+                let col = colors.as_ref().map_or_else(|| x.draw_color(max_speed), |c| c[i]);
+                c.circle(x.pos.x * sx, x.pos.y * sy, 2.8 * sx, col);
+            }
+        }
+
+        let mut tot = 0.0;
+        let width = 1000.0;
+        let height = 20.0;
+        for (i, share) in col.iter().enumerate() {
+            let mut rgb = [0.0, 0.0, 0.0, 1.0];
+            rgb[i] = 1.0;
+            c.rect((10.0 + tot * width) * sx, 10.0 * sy, share * width * sx, height * sy, rgb);
+            tot += share;
+        }
+    }
+}
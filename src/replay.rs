@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use ggez::input::mouse::MouseButton;
+use serde::{Serialize, Deserialize};
+use crate::ag::Agent;
+
+// A session file is a JSON object per line: the header, then every input
+// with the number of steps simulated when it arrived. Replaying it from the
+// same seed feeds the inputs back at the same steps.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub seed: u64,
+    pub w: f32,
+    pub h: f32,
+    pub agents: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Input {
+    MouseMove { x: f32, y: f32, dx: f32, dy: f32 },
+    MouseDown { button: String, x: f32, y: f32 },
+    MouseUp { button: String, x: f32, y: f32 },
+    Wheel { y: f32 },
+    // Key with modifiers, as written in the bindings, e.g. "Ctrl+Z"
+    Key { key: String },
+    // Results of things that depend on the machine rather than on the input
+    LatexDiv { value: f32 },
+    Config { table: String, changed: Vec<String> },
+    // Written when the recording stops, to check the replay against
+    End { hash: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub step: u64,
+    #[serde(flatten)]
+    pub input: Input,
+}
+
+pub fn button_name(b: MouseButton) -> Option<String> {
+    match b {
+        MouseButton::Left => Some("left".to_string()),
+        MouseButton::Right => Some("right".to_string()),
+        MouseButton::Middle => Some("middle".to_string()),
+        MouseButton::Other(_) => None,
+    }
+}
+
+pub fn parse_button(s: &str) -> Option<MouseButton> {
+    match s {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        _ => None,
+    }
+}
+
+// FNV-1a of everything that defines the state of the agents
+pub fn hash(agents: &[Agent]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    let mut eat = |x: u32| {
+        for b in x.to_le_bytes().iter() {
+            h ^= *b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    };
+    eat(agents.len() as u32);
+    for x in agents.iter() {
+        [x.pos.x, x.pos.y, x.vel.x, x.vel.y, x.color[0], x.color[1], x.color[2]].iter().for_each(|v| eat(v.to_bits()));
+        eat(x.frozen as u32);
+    }
+    h
+}
+
+pub struct Recorder {
+    pub path: String,
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str, header: &Header) -> Result<Recorder, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut r = Recorder { path: path.to_string(), out: BufWriter::new(file) };
+        r.write(&serde_json::to_string(header).map_err(|e| e.to_string())?);
+        Ok(r)
+    }
+
+    fn write(&mut self, line: &str) {
+        // A failing disk shouldn't stop the simulation
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    pub fn record(&mut self, step: u64, input: &Input) {
+        if let Ok(line) = serde_json::to_string(&Entry { step, input: input.clone() }) {
+            self.write(&line);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+pub struct Player {
+    pub path: String,
+    pub header: Header,
+    entries: VecDeque<Entry>,
+}
+
+impl Player {
+    pub fn load(path: &str) -> Result<Player, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut lines = BufReader::new(file).lines();
+        let first = lines.next().ok_or("empty session file")?.map_err(|e| e.to_string())?;
+        let header = serde_json::from_str(&first).map_err(|e| format!("bad header: {}", e))?;
+        let mut entries = VecDeque::new();
+        for (i, line) in lines.enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() { continue; }
+            entries.push_back(serde_json::from_str(&line).map_err(|e| format!("line {}: {}", i + 2, e))?);
+        }
+        Ok(Player { path: path.to_string(), header, entries })
+    }
+
+    // The next input due at `step`, inputs come out in recording order
+    pub fn next(&mut self, step: u64) -> Option<Input> {
+        if self.entries.front()?.step <= step {
+            self.entries.pop_front().map(|e| e.input)
+        } else {
+            None
+        }
+    }
+
+    // Steps that can run before the next input is due
+    pub fn steps_left(&self, step: u64) -> Option<u64> {
+        self.entries.front().map(|e| e.step.saturating_sub(step))
+    }
+}
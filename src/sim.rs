@@ -0,0 +1,200 @@
+use rayon::prelude::*;
+use serde_json::json;
+use crate::ag;
+use crate::clock;
+use crate::field;
+use crate::gravity;
+use crate::integrator;
+use crate::latex::Latex2D;
+use crate::params;
+use crate::profiler;
+use crate::utils;
+use crate::vec;
+
+// Steps between two energy drift reports
+const ENERGY_EVERY: u64 = 300;
+// Frames the max speed is averaged over before scaling the agents opacity
+pub const ST_LEN: usize = 40;
+
+// The world and everything that moves it. Knows nothing about the window, so
+// the same steps run in the ggez game and in headless mode.
+pub struct Sim {
+    pub w: f32,
+    pub h: f32,
+    pub agents: Vec<ag::Agent>,
+    pub latex: Latex2D<ag::Agent>,
+    pub latex_div: f32,
+    pub wells: Vec<gravity::Well>,
+    pub fields: field::Fields,
+    pub params: params::Params,
+    pub clock: clock::Clock,
+    pub integrator: integrator::Integrator,
+    pub energy: integrator::EnergyReport,
+    pub adaptive: clock::Adaptive,
+}
+
+impl Sim {
+    // `n` agents at random positions, standing still
+    pub fn new(w: f32, h: f32, n: usize, clock: clock::Clock) -> Sim {
+        let mut agents = vec![];
+        while agents.len() < n {
+            agents.push(ag::Agent::new(
+                agents.len(),
+                vec::Vec {
+                    x: utils::rand_float(0.0, w),
+                    y: utils::rand_float(0.0, h),
+                },
+                vec::Vec {
+                    x: 0.0,//rand::thread_rng().gen_range(-1.0, 1.0),
+                    y: 0.0,//rand::thread_rng().gen_range(-1.0, 1.0),
+                },
+            ))
+        }
+        let mut sim = Sim {
+            w,
+            h,
+            agents,
+            latex: Latex2D::new(0.0, 0.0, 0.0),
+            latex_div: 4.0,
+            wells: vec![],
+            fields: field::Fields::new(w, h),
+            params: params::Params::default(),
+            clock,
+            integrator: integrator::Integrator::Euler,
+            energy: integrator::EnergyReport::default(),
+            adaptive: clock::Adaptive::new(),
+        };
+        sim.update_latex();
+        sim
+    }
+
+    // Propagate a parameter to what was derived from it, e.g. gravity_f sets
+    // the strength of every well
+    pub fn param_changed(&mut self, name: &str) {
+        if name == "gravity_f" {
+            let s = gravity::strength(self.params.gravity_f);
+            self.wells.iter_mut().for_each(|g| g.strength = s.copysign(g.strength));
+            self.energy.reset();
+        }
+    }
+
+    // Pick the latex cell size that steps fastest on this machine. The agents
+    // are left as they were.
+    pub fn tune_latex_div(&mut self) -> f32 {
+        let mut min: Option<(f64, f32)> = None;
+        let ag = self.agents.clone();
+        let mouse = vec::Vec::new();
+        for ld in 10..70 {
+            self.agents = ag.clone();
+            self.latex_div = ld as f32;
+            // Boot up
+            self.step(mouse);
+
+            // Measure
+            let t_start = utils::now();
+            for _ in 0..3 {
+                self.step(mouse);
+            }
+            let t_diff = utils::now() - t_start;
+
+            // Compare
+            utils::log("latex_div", json!({ "div": ld, "time": t_diff }));
+            if !min.is_none() && min.unwrap().0 < t_diff {
+                break
+            }
+            if min.is_none() || min.unwrap().0 > t_diff {
+                min = Some((t_diff, ld as f32));
+            }
+        }
+        utils::log("latex_div_best", json!({ "div": min.unwrap().1, "time": min.unwrap().0 }));
+        self.agents = ag;
+        self.latex_div = min.unwrap().1 + 6.0;
+        self.update_latex();
+        self.energy.reset();
+        self.latex_div
+    }
+
+    // The agents where they are now, `latex` keeps them as they were at the
+    // start of the last step
+    pub fn neighbours(&self) -> Latex2D<ag::Agent> {
+        let mut latex = Latex2D::new(self.w / 2.0 / self.latex_div, self.w, self.h);
+        let _t0 = utils::now();
+        self.agents.iter().for_each(|x| latex.add((x.pos.x, x.pos.y), x.clone()));
+        // println!("latex:   {:.3}", utils::now() - _t0);
+        latex
+    }
+
+    pub fn update_latex(&mut self) {
+        self.latex = self.neighbours();
+    }
+
+    // Advance the simulation by exactly one step of `clock.dt`, split into
+    // several substeps when the adaptive timestep asks for it. `mouse` is
+    // where the wells following the cursor are.
+    pub fn step(&mut self, mouse: vec::Vec) {
+        let (w, h) = (self.w, self.h);
+
+        self.agents.par_iter_mut().for_each(|x| x.prev_pos = x.pos);
+
+        // Velocities as they are now, a brush may have pushed the agents since
+        // the last step, the accelerations from the last step
+        let substeps = if self.adaptive.enabled {
+            let (max_vel, max_acc, min_range) = self.agents.par_iter()
+                .fold(|| (0.0, 0.0, f32::MAX), |v: (f32, f32, f32), x| (v.0.max(x.vel.mag()), v.1.max(x.s_acc), v.2.min(x.view_range)))
+                .reduce(|| (0.0, 0.0, f32::MAX), |v, x| (v.0.max(x.0), v.1.max(x.1), v.2.min(x.2)));
+            self.adaptive.substeps(self.clock.dt, max_vel, max_acc, min_range)
+        } else {
+            1
+        };
+        let dt = self.clock.dt / substeps as f32;
+
+        for i in 0..substeps {
+            let _p = profiler::scope("update");
+            let p = profiler::scope("latex");
+            self.update_latex();
+            drop(p);
+
+            let time = self.clock.time as f32 + i as f32 * dt;
+
+            let update = ag::Update {
+                w,
+                h,
+                dt,
+                time,
+                agents: &self.latex,
+                gravity: self.wells.iter().map(|g| g.at(time, mouse)).collect(),
+                fields: &self.fields,
+                integrator: self.integrator,
+                params: &self.params,
+            };
+
+            let p = profiler::scope("agents");
+            self.agents.par_iter_mut().for_each(|x| x.update(&update));
+            drop(p);
+
+            if i + 1 == substeps && self.clock.steps.is_multiple_of(ENERGY_EVERY) {
+                let e = update.energy(&self.agents);
+                if let Some(drift) = self.energy.sample(self.clock.steps, e) {
+                    utils::log("energy", json!({
+                        "step": self.clock.steps,
+                        "energy": e,
+                        "drift_per_1000": drift,
+                        "integrator": format!("{:?}", self.integrator),
+                    }));
+                }
+            }
+        }
+    }
+
+    // Largest agent speed and colour share, as shown by the stats bar
+    pub fn stats(&self) -> (f32, [f32; 3]) {
+        let max_speed: f32 = self.agents.par_iter()
+            .fold(|| 0.0, |v: f32, x| v.max(x.s_vel))
+            .reduce(|| 0.0, |v: f32, x| v.max(x));
+        let mut col: [f32; 3] = self.agents.par_iter()
+            .fold(|| [0.0, 0.0, 0.0], |v, x| utils::sum(&v, &x.color))
+            .reduce(|| [0.0, 0.0, 0.0], |v, x| utils::sum(&v, &x));
+        utils::softmax_fast(&mut col);
+        (max_speed, col)
+    }
+}
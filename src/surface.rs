@@ -0,0 +1,332 @@
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::nalgebra::Point2;
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use crate::ag::Agent;
+use crate::latex::Latex2D;
+use crate::output::Sink;
+use crate::raster::{Canvas, Renderer};
+use crate::sim::Sim;
+use crate::vec;
+
+// Draws the fluid as filled regions where the agents are dense enough,
+// instead of one dot per agent
+#[derive(Clone, Debug)]
+pub struct Surface {
+    pub enabled: bool,
+    // Grid spacing and reach of every agent, in pixels of the world
+    pub cell: f32,
+    pub radius: f32,
+    // Field level of the edge, a lone agent peaks at 1
+    pub iso: f32,
+}
+
+impl Default for Surface {
+    fn default() -> Surface {
+        Surface { enabled: false, cell: 6.0, radius: 12.0, iso: 0.6 }
+    }
+}
+
+// Sum of the metaballs of the agents sampled on the corners of a grid, with
+// their colours averaged the same way
+pub struct Field {
+    pub nx: usize,
+    pub ny: usize,
+    pub cell: f32,
+    pub values: Vec<f32>,
+    colors: Vec<[f32; 3]>,
+}
+
+impl Field {
+    pub fn build(sim: &Sim, latex: &Latex2D<Agent>, cell: f32, radius: f32) -> Field {
+        let nx = ((sim.w / cell).ceil() as usize).max(1);
+        let ny = ((sim.h / cell).ceil() as usize).max(1);
+        let (pw, ph) = sim.fields.period(sim.w, sim.h);
+        let (values, colors) = (0..(nx + 1) * (ny + 1)).into_par_iter().map(|i| {
+            let p = vec::Vec::new_from((i % (nx + 1)) as f32 * cell, (i / (nx + 1)) as f32 * cell);
+            let mut v = 0.0;
+            let mut c = [0.0; 3];
+            for a in latex.get((p.x, p.y), radius).iter() {
+                let d = p.dist_mod(&a.pos, pw, ph);
+                if d >= radius { continue; }
+                let k = (1.0 - (d / radius).powi(2)).powi(2);
+                v += k;
+                (0..3).for_each(|j| c[j] += a.color[j] * k);
+            }
+            if v > 0.0 { (0..3).for_each(|j| c[j] /= v); }
+            (v, c)
+        }).unzip();
+        Field { nx, ny, cell, values, colors }
+    }
+
+    fn index(&self, i: usize, j: usize) -> usize {
+        j.min(self.ny) * (self.nx + 1) + i.min(self.nx)
+    }
+
+    fn pos(&self, i: usize, j: usize) -> [f32; 2] {
+        [i as f32 * self.cell, j as f32 * self.cell]
+    }
+
+    // Bilinear interpolation of the values and colours at `(x, y)`
+    pub fn at(&self, x: f32, y: f32) -> (f32, [f32; 3]) {
+        let gx = (x / self.cell).clamp(0.0, self.nx as f32);
+        let gy = (y / self.cell).clamp(0.0, self.ny as f32);
+        let (i, j) = (gx.floor() as usize, gy.floor() as usize);
+        let (fx, fy) = (gx - i as f32, gy - j as f32);
+        let w = [(1.0 - fx) * (1.0 - fy), fx * (1.0 - fy), (1.0 - fx) * fy, fx * fy];
+        let n = [self.index(i, j), self.index(i + 1, j), self.index(i, j + 1), self.index(i + 1, j + 1)];
+        let mut v = 0.0;
+        let mut c = [0.0; 3];
+        for (k, n) in n.iter().enumerate() {
+            v += self.values[*n] * w[k];
+            (0..3).for_each(|j| c[j] += self.colors[*n][j] * w[k]);
+        }
+        (v, c)
+    }
+}
+
+// Corners of cell (i, j) clockwise from the top left, and the edge from each
+// one to the next as an id shared with the neighbouring cell: twice the index
+// of its first node, plus one if it is vertical
+fn corners(i: usize, j: usize) -> [(usize, usize); 4] {
+    [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)]
+}
+
+fn edges(f: &Field, i: usize, j: usize) -> [usize; 4] {
+    [2 * f.index(i, j), 2 * f.index(i + 1, j) + 1, 2 * f.index(i, j + 1), 2 * f.index(i, j) + 1]
+}
+
+// Where the field crosses `iso` along an edge
+fn crossing(f: &Field, edge: usize, iso: f32) -> [f32; 2] {
+    let n = edge / 2;
+    let (i, j) = (n % (f.nx + 1), n / (f.nx + 1));
+    let (k, l) = if edge.is_multiple_of(2) { (i + 1, j) } else { (i, j + 1) };
+    let (va, vb) = (f.values[n], f.values[f.index(k, l)]);
+    let t = if vb != va { ((iso - va) / (vb - va)).clamp(0.0, 1.0) } else { 0.5 };
+    let (a, b) = (f.pos(i, j), f.pos(k, l));
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+}
+
+// Which corners of cell (i, j) are inside, and whether it is a saddle where
+// the two inside corners are apart, the centre being outside
+fn classify(f: &Field, i: usize, j: usize, iso: f32) -> ([bool; 4], bool) {
+    let v = corners(i, j).map(|c| f.values[f.index(c.0, c.1)]);
+    let inside = v.map(|v| v >= iso);
+    let saddle = (0..4).all(|k| inside[k] != inside[(k + 1) % 4]);
+    (inside, saddle && v.iter().sum::<f32>() / 4.0 < iso)
+}
+
+// Contour segments of a cell as pairs of edge ids
+fn segments(f: &Field, i: usize, j: usize, iso: f32) -> Vec<(usize, usize)> {
+    let e = edges(f, i, j);
+    let (inside, apart) = classify(f, i, j, iso);
+    let crossed: Vec<usize> = (0..4).filter(|k| inside[*k] != inside[(k + 1) % 4]).collect();
+    if crossed.len() == 2 {
+        return vec![(e[crossed[0]], e[crossed[1]])];
+    }
+    // In a saddle a segment cuts off every corner on the side that is apart,
+    // between the edge before the corner and its own
+    (0..4).filter(|k| crossed.len() == 4 && inside[*k] == apart)
+        .map(|k| (e[(k + 3) % 4], e[k]))
+        .collect()
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Contour {
+    // Open where it leaves the world
+    pub closed: bool,
+    // Enclosed area, closed contours only
+    pub area: Option<f32>,
+    pub points: Vec<[f32; 2]>,
+}
+
+// Polylines along which the field equals `iso`, marching squares
+pub fn contours(f: &Field, iso: f32) -> Vec<Contour> {
+    let mut segs = vec![];
+    for j in 0..f.ny {
+        for i in 0..f.nx {
+            segs.extend(segments(f, i, j, iso));
+        }
+    }
+    // The segments touching every edge, at most one per side
+    let mut by_edge = vec![[usize::MAX; 2]; 2 * (f.nx + 1) * (f.ny + 1)];
+    for (s, (a, b)) in segs.iter().enumerate() {
+        for e in [a, b].iter() {
+            let slot = if by_edge[**e][0] == usize::MAX { 0 } else { 1 };
+            by_edge[**e][slot] = s;
+        }
+    }
+    let next = |e: usize, from: usize| by_edge[e].iter().cloned().find(|s| *s != from && *s != usize::MAX);
+
+    let mut used = vec![false; segs.len()];
+    let mut out = vec![];
+    for start in 0..segs.len() {
+        if used[start] { continue; }
+        used[start] = true;
+        let mut chain = vec![segs[start].0, segs[start].1];
+        // Forward from the end, then backward from the start if still open
+        for forward in [true, false].iter() {
+            let mut s = start;
+            loop {
+                let e = if *forward { *chain.last().unwrap() } else { chain[0] };
+                let n = match next(e, s) { Some(n) if !used[n] => n, _ => break };
+                used[n] = true;
+                let o = if segs[n].0 == e { segs[n].1 } else { segs[n].0 };
+                if *forward { chain.push(o) } else { chain.insert(0, o) }
+                s = n;
+            }
+        }
+        let closed = chain.len() > 3 && chain[0] == *chain.last().unwrap();
+        if closed { chain.pop(); }
+        let points: Vec<[f32; 2]> = chain.iter().map(|e| crossing(f, *e, iso)).collect();
+        let area = if closed {
+            let twice: f32 = (0..points.len()).map(|k| {
+                let (p, q) = (points[k], points[(k + 1) % points.len()]);
+                p[0] * q[1] - q[0] * p[1]
+            }).sum();
+            Some(twice.abs() / 2.0)
+        } else {
+            None
+        };
+        out.push(Contour { closed, area, points });
+    }
+    out
+}
+
+// The inside part of every cell as a polygon around the cell, star shaped
+// from its centroid
+pub fn cells(f: &Field, iso: f32) -> Vec<Vec<[f32; 2]>> {
+    let mut out = vec![];
+    for j in 0..f.ny {
+        for i in 0..f.nx {
+            let c = corners(i, j);
+            let e = edges(f, i, j);
+            let (inside, apart) = classify(f, i, j, iso);
+            if !inside.iter().any(|x| *x) { continue; }
+            if apart {
+                // Two separate corners
+                for k in (0..4).filter(|k| inside[*k]) {
+                    out.push(vec![crossing(f, e[(k + 3) % 4], iso), f.pos(c[k].0, c[k].1), crossing(f, e[k], iso)]);
+                }
+                continue;
+            }
+            let mut poly = vec![];
+            for k in 0..4 {
+                if inside[k] { poly.push(f.pos(c[k].0, c[k].1)); }
+                if inside[k] != inside[(k + 1) % 4] { poly.push(crossing(f, e[k], iso)); }
+            }
+            out.push(poly);
+        }
+    }
+    out
+}
+
+// Fills the canvas where the field is above `iso`, with the edge blended
+// over about a pixel
+pub fn paint(canvas: &mut Canvas, f: &Field, iso: f32, sx: f32, sy: f32) {
+    let w = canvas.w;
+    let px: Vec<Option<([f32; 4], f32)>> = (0..canvas.w * canvas.h).into_par_iter().map(|i| {
+        let (x, y) = (((i % w) as f32 + 0.5) / sx, ((i / w) as f32 + 0.5) / sy);
+        let (v, c) = f.at(x, y);
+        let slope = (f.at(x + 1.0 / sx, y).0 - v).abs() + (f.at(x, y + 1.0 / sy).0 - v).abs();
+        let cover = ((v - iso) / slope.max(1e-6) + 0.5).clamp(0.0, 1.0);
+        if cover > 0.0 { Some(([c[0], c[1], c[2], 1.0], cover)) } else { None }
+    }).collect();
+    for (i, p) in px.into_iter().enumerate() {
+        if let Some((col, cover)) = p {
+            canvas.blend(i % w, i / w, col, cover);
+        }
+    }
+}
+
+// Raw vertices take linear colours, unlike the rest of ggez
+fn linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+impl Surface {
+    pub fn field(&self, sim: &Sim, latex: &Latex2D<Agent>) -> Field {
+        Field::build(sim, latex, self.cell, self.radius)
+    }
+
+    // Cells filled with the colours of the agents, shaded between vertices,
+    // and the contours on top to soften the edge
+    pub fn draw(&self, ctx: &mut Context, sim: &Sim, latex: &Latex2D<Agent>) -> GameResult<()> {
+        let f = self.field(sim, latex);
+        let mut verts = vec![];
+        let mut indices = vec![];
+        for poly in cells(&f, self.iso).iter() {
+            let n = poly.len() as f32;
+            let mid = [poly.iter().map(|p| p[0]).sum::<f32>() / n, poly.iter().map(|p| p[1]).sum::<f32>() / n];
+            let first = verts.len() as u32;
+            for p in std::iter::once(&mid).chain(poly.iter()) {
+                let [r, g, b] = f.at(p[0], p[1]).1.map(linear);
+                verts.push(graphics::Vertex { pos: *p, uv: *p, color: [r, g, b, 1.0] });
+            }
+            for k in 0..poly.len() as u32 {
+                indices.extend_from_slice(&[first, first + 1 + k, first + 1 + (k + 1) % poly.len() as u32]);
+            }
+        }
+        if indices.is_empty() { return Ok(()); }
+        let mut mb = graphics::MeshBuilder::new();
+        mb.raw(&verts, &indices, None);
+        let col = graphics::Color::new(1.0, 1.0, 1.0, 0.35);
+        for c in contours(&f, self.iso).iter() {
+            let mut points: Vec<Point2<f32>> = c.points.iter().map(|p| Point2::new(p[0], p[1])).collect();
+            if c.closed { points.push(points[0]); }
+            if points.len() > 1 { mb.line(&points, 1.0, col)?; }
+        }
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::new())
+    }
+
+    pub fn to_json(&self, sim: &Sim) -> serde_json::Value {
+        let contours = contours(&self.field(sim, &sim.neighbours()), self.iso);
+        json!({
+            "step": sim.clock.steps,
+            "time": sim.clock.time,
+            "w": sim.w,
+            "h": sim.h,
+            "cell": self.cell,
+            "radius": self.radius,
+            "iso": self.iso,
+            "contours": contours,
+        })
+    }
+
+    // Returns the number of contours written
+    pub fn save(&self, path: &str, sim: &Sim) -> Result<usize, String> {
+        let doc = self.to_json(sim);
+        let n = doc["contours"].as_array().map_or(0, |c| c.len());
+        std::fs::write(path, format!("{}\n", doc)).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(n)
+    }
+}
+
+// contours_00000.json, ... with the contours of every frame
+pub struct Contours {
+    dir: String,
+    surface: Surface,
+    frame: usize,
+}
+
+impl Contours {
+    pub fn new(dir: &str, surface: Surface) -> Result<Contours, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        Ok(Contours { dir: dir.to_string(), surface, frame: 0 })
+    }
+}
+
+impl Sink for Contours {
+    fn needs_canvas(&self) -> bool {
+        false
+    }
+
+    fn frame(&mut self, sim: &Sim, _renderer: &Renderer) -> Result<(), String> {
+        self.surface.save(&format!("{}/contours_{:05}.json", self.dir, self.frame), sim)?;
+        self.frame += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+use crate::editor;
+use crate::sim::Sim;
+use crate::vec;
+
+// Length in pixels of the arrow of the fastest agent
+const ARROW_LEN: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // Velocity arrows on every agent
+    pub arrows: bool,
+}
+
+fn rgb(c: [f32; 4]) -> String {
+    let b = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", b(c[0]), b(c[1]), b(c[2]))
+}
+
+// The agents as a vector figure: circles with the colour and the speed based
+// opacity of the window, the wells and the enabled fields on top and a legend.
+// `max_speed` is the speed shown at full opacity, `mouse` where the wells
+// following the cursor are.
+pub fn render(sim: &Sim, max_speed: f32, mouse: vec::Vec, opts: &Options) -> String {
+    let (w, h) = (sim.w, sim.h);
+    let mut s = String::new();
+    let _ = writeln!(s, r##"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"##, w, h, w, h);
+    let _ = writeln!(s, r##"<defs><marker id="head" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="4" markerHeight="4" orient="auto"><path d="M0,0 L10,5 L0,10 z" fill="context-stroke"/></marker></defs>"##);
+    let _ = writeln!(s, r##"<rect width="{}" height="{}" fill="#000"/>"##, w, h);
+
+    let _ = writeln!(s, r##"<g id="agents">"##);
+    for x in sim.agents.iter() {
+        let c = x.draw_color(max_speed);
+        let _ = writeln!(s, r##"<circle cx="{:.2}" cy="{:.2}" r="2.8" fill="{}" fill-opacity="{:.3}"/>"##, x.pos.x, x.pos.y, rgb(c), c[3].min(1.0));
+    }
+    let _ = writeln!(s, "</g>");
+
+    if opts.arrows && max_speed > 0.0 {
+        let k = ARROW_LEN / max_speed;
+        let _ = writeln!(s, r##"<g id="velocities" stroke-width="0.8" marker-end="url(#head)">"##);
+        for x in sim.agents.iter().filter(|x| x.s_vel > 0.0) {
+            let c = x.draw_color(max_speed);
+            let _ = writeln!(s, r##"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke="{}" stroke-opacity="{:.3}"/>"##,
+                x.pos.x, x.pos.y, x.pos.x + x.vel.x * k, x.pos.y + x.vel.y * k, rgb(c), c[3].min(1.0));
+        }
+        let _ = writeln!(s, "</g>");
+    }
+
+    let _ = writeln!(s, r##"<g id="overlays" fill="none" stroke-width="1">"##);
+    if sim.fields.wind.enabled {
+        for z in sim.fields.wind.zones.iter() {
+            let _ = writeln!(s, r##"<rect x="{}" y="{}" width="{}" height="{}" stroke="#8f8" stroke-dasharray="6 4"/>"##, z.rect[0], z.rect[1], z.rect[2], z.rect[3]);
+        }
+    }
+    if sim.fields.vortices.enabled {
+        for v in sim.fields.vortices.list.iter() {
+            let _ = writeln!(s, r##"<circle cx="{}" cy="{}" r="{}" stroke="#f8f" stroke-dasharray="6 4"/>"##, v.pos.x, v.pos.y, v.radius);
+        }
+    }
+    for well in sim.wells.iter() {
+        let at = well.at(sim.clock.time as f32, mouse);
+        let col = if well.strength >= 0.0 { "#ff991a" } else { "#33ccff" };
+        let _ = writeln!(s, r##"<circle cx="{:.2}" cy="{:.2}" r="4" fill="{}" stroke="none"/>"##, at.pos.x, at.pos.y, col);
+        let _ = writeln!(s, r##"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" stroke="{}"/>"##, at.pos.x, at.pos.y, editor::ring_radius(well), col);
+    }
+    let _ = writeln!(s, "</g>");
+
+    legend(&mut s, sim, max_speed, opts);
+    s.push_str("</svg>\n");
+    s
+}
+
+fn legend(s: &mut String, sim: &Sim, max_speed: f32, opts: &Options) {
+    let (_, share) = sim.stats();
+    let mut rows: Vec<(String, String)> = vec![];
+    for (i, name) in ["red", "green", "blue"].iter().enumerate() {
+        let mut c = [0.0, 0.0, 0.0, 1.0];
+        c[i] = 1.0;
+        rows.push((
+            format!(r##"<circle r="4" fill="{}"/>"##, rgb(c)),
+            format!("{} {:.0}%", name, share[i] * 100.0),
+        ));
+    }
+    rows.push((
+        r##"<circle r="4" fill="#fff" fill-opacity="0.5"/>"##.to_string(),
+        "at rest".to_string(),
+    ));
+    rows.push((
+        r##"<circle r="4" fill="#fff"/>"##.to_string(),
+        format!("speed {:.2}+", max_speed / 1.5),
+    ));
+    if opts.arrows {
+        rows.push((
+            format!(r##"<line x1="-6" x2="{}" stroke="#fff" marker-end="url(#head)"/>"##, ARROW_LEN - 6.0),
+            format!("speed {:.2}", max_speed),
+        ));
+    }
+    if sim.wells.iter().any(|w| w.strength >= 0.0) {
+        rows.push((r##"<circle r="4" fill="#ff991a"/>"##.to_string(), "attracting well".to_string()));
+    }
+    if sim.wells.iter().any(|w| w.strength < 0.0) {
+        rows.push((r##"<circle r="4" fill="#33ccff"/>"##.to_string(), "repelling well".to_string()));
+    }
+    if sim.fields.wind.enabled && !sim.fields.wind.zones.is_empty() {
+        rows.push((r##"<rect x="-5" y="-4" width="10" height="8" fill="none" stroke="#8f8" stroke-dasharray="3 2"/>"##.to_string(), "wind zone".to_string()));
+    }
+    if sim.fields.vortices.enabled && !sim.fields.vortices.list.is_empty() {
+        rows.push((r##"<circle r="5" fill="none" stroke="#f8f" stroke-dasharray="3 2"/>"##.to_string(), "vortex".to_string()));
+    }
+
+    let row = 16.0;
+    let top = sim.h - 10.0 - rows.len() as f32 * row - 8.0;
+    let _ = writeln!(s, r##"<g id="legend" transform="translate(10 {})" font-family="sans-serif" font-size="11" fill="#fff">"##, top);
+    let _ = writeln!(s, r##"<rect width="150" height="{}" fill="#000" fill-opacity="0.6" stroke="#666"/>"##, rows.len() as f32 * row + 8.0);
+    for (i, (symbol, label)) in rows.iter().enumerate() {
+        let y = 4.0 + row * (i as f32 + 0.5);
+        let _ = writeln!(s, r##"<g transform="translate(14 {})">{}</g><text x="28" y="{}" dominant-baseline="middle">{}</text>"##, y, symbol, y, label);
+    }
+    let _ = writeln!(s, "</g>");
+}
+
+pub fn save(path: &str, svg: &str) -> Result<(), String> {
+    std::fs::write(path, svg).map_err(|e| format!("{}: {}", path, e))
+}
@@ -0,0 +1,199 @@
+use std::io::{BufWriter, Stdout, Write};
+use crate::output::Sink;
+use crate::raster::Renderer;
+use crate::sim::Sim;
+use crate::utils;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    // 2x4 dots per character, one colour each
+    Braille,
+    // Upper half block, two colours per character
+    Blocks,
+}
+
+impl Style {
+    pub fn parse(s: &str) -> Result<Style, String> {
+        match s {
+            "braille" => Ok(Style::Braille),
+            "blocks" => Ok(Style::Blocks),
+            _ => Err(format!("unknown terminal style '{}', use braille or blocks", s)),
+        }
+    }
+
+    // Dots per character
+    fn cell(self) -> (usize, usize) {
+        match self {
+            Style::Braille => (2, 4),
+            Style::Blocks => (1, 2),
+        }
+    }
+}
+
+// Columns and rows of the terminal: $COLUMNS and $LINES, else what stty says,
+// else 80x24
+pub fn size() -> (usize, usize) {
+    let env = |k| std::env::var(k).ok().and_then(|v| v.parse::<usize>().ok());
+    if let (Some(c), Some(r)) = (env("COLUMNS"), env("LINES")) {
+        return (c, r);
+    }
+    let stty = std::process::Command::new("stty")
+        .arg("size")
+        .stdin(std::process::Stdio::inherit())
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .and_then(|s| {
+            let mut it = s.split_whitespace().map(|n| n.parse::<usize>().ok());
+            match (it.next()?, it.next()?) {
+                (Some(r), Some(c)) => Some((c, r)),
+                _ => None,
+            }
+        });
+    stty.unwrap_or((80, 24))
+}
+
+// Agents per dot and their summed colour
+struct Grid {
+    w: usize,
+    h: usize,
+    count: Vec<f32>,
+    color: Vec<[f32; 3]>,
+}
+
+impl Grid {
+    fn new(sim: &Sim, w: usize, h: usize) -> Grid {
+        let mut g = Grid { w, h, count: vec![0.0; w * h], color: vec![[0.0; 3]; w * h] };
+        for x in sim.agents.iter() {
+            let gx = ((x.pos.x / sim.w * w as f32) as usize).min(w - 1);
+            let gy = ((x.pos.y / sim.h * h as f32) as usize).min(h - 1);
+            let i = gy * w + gx;
+            g.count[i] += 1.0;
+            (0..3).for_each(|c| g.color[i][c] += x.color[c]);
+        }
+        g
+    }
+
+    // Average colour of the agents in the dots, stretched so the dominant
+    // channel is at full brightness, then dimmed where they are sparse
+    fn rgb(&self, dots: &[usize], mean: f32) -> [u8; 3] {
+        let n: f32 = dots.iter().map(|&i| self.count[i]).sum();
+        let mut c = [0.0f32; 3];
+        dots.iter().for_each(|&i| (0..3).for_each(|k| c[k] += self.color[i][k]));
+        let top = c[0].max(c[1]).max(c[2]);
+        if n == 0.0 || top <= 0.0 {
+            return [0, 0, 0];
+        }
+        let light = (0.35 + 0.65 * n / (dots.len() as f32 * mean * 2.0)).min(1.0);
+        let b = |x: f32| (x / top * light * 255.0).round() as u8;
+        [b(c[0]), b(c[1]), b(c[2])]
+    }
+}
+
+// Draws the density and dominant colour of the agents with ANSI truecolour,
+// in place, at most `fps` times per second.
+pub struct Terminal {
+    style: Style,
+    cols: usize,
+    rows: usize,
+    fps: f32,
+    last: f64,
+    out: BufWriter<Stdout>,
+}
+
+impl Terminal {
+    pub fn new(style: Style, (cols, rows): (usize, usize), fps: f32) -> Terminal {
+        Terminal {
+            style,
+            cols: cols.max(1),
+            // The last row is the status line
+            rows: rows.max(2) - 1,
+            fps,
+            last: 0.0,
+            out: BufWriter::new(std::io::stdout()),
+        }
+    }
+
+    pub fn render(&self, sim: &Sim) -> String {
+        let (dx, dy) = self.style.cell();
+        let grid = Grid::new(sim, self.cols * dx, self.rows * dy);
+        let mean = (sim.agents.len() as f32 / (grid.w * grid.h) as f32).max(1e-6);
+        // A dot lights up once it has half the agents of an average one
+        let lit = (mean * 0.5).max(1.0);
+
+        let mut s = String::from("\x1b[H");
+        for row in 0..self.rows {
+            let mut fg = None;
+            let mut bg = None;
+            for col in 0..self.cols {
+                let dot = |x: usize, y: usize| (row * dy + y) * grid.w + col * dx + x;
+                match self.style {
+                    Style::Braille => {
+                        // Unicode braille numbers the dots down the left column first
+                        const BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                        let mut dots = vec![];
+                        let mut ch = 0x2800;
+                        for (x, bits) in BITS.iter().enumerate() {
+                            for (y, bit) in bits.iter().enumerate() {
+                                dots.push(dot(x, y));
+                                if grid.count[dot(x, y)] >= lit { ch |= bit; }
+                            }
+                        }
+                        let c = grid.rgb(&dots, mean);
+                        if fg != Some(c) {
+                            s.push_str(&format!("\x1b[38;2;{};{};{}m", c[0], c[1], c[2]));
+                            fg = Some(c);
+                        }
+                        s.push(std::char::from_u32(ch).unwrap_or(' '));
+                    },
+                    Style::Blocks => {
+                        let top = grid.rgb(&[dot(0, 0)], mean);
+                        let bottom = grid.rgb(&[dot(0, 1)], mean);
+                        if fg != Some(top) {
+                            s.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+                            fg = Some(top);
+                        }
+                        if bg != Some(bottom) {
+                            s.push_str(&format!("\x1b[48;2;{};{};{}m", bottom[0], bottom[1], bottom[2]));
+                            bg = Some(bottom);
+                        }
+                        s.push('▀');
+                    },
+                }
+            }
+            s.push_str("\x1b[0m\r\n");
+        }
+        let (_, share) = sim.stats();
+        let status = format!("step {}  agents {}  r {:.0}% g {:.0}% b {:.0}%",
+            sim.clock.steps, sim.agents.len(), share[0] * 100.0, share[1] * 100.0, share[2] * 100.0);
+        s.push_str(&status.chars().take(self.cols).collect::<String>());
+        s.push_str("\x1b[K");
+        s
+    }
+}
+
+impl Sink for Terminal {
+    fn needs_canvas(&self) -> bool {
+        false
+    }
+
+    fn frame(&mut self, sim: &Sim, _renderer: &Renderer) -> Result<(), String> {
+        let now = utils::now();
+        if now - self.last < 1.0 / self.fps as f64 {
+            return Ok(());
+        }
+        if self.last == 0.0 {
+            // Start from a clean screen, later frames overwrite each other
+            let _ = write!(self.out, "\x1b[2J");
+        }
+        self.last = now;
+        let frame = self.render(sim);
+        self.out.write_all(frame.as_bytes())
+            .and_then(|_| self.out.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        writeln!(self.out, "\x1b[0m").and_then(|_| self.out.flush()).map_err(|e| e.to_string())
+    }
+}
@@ -1,58 +1,35 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-#[allow(dead_code)]
-pub struct Timer {
-    times: Vec<(String, f64)>,
-}
+// Every random number comes from here, so a run can be reproduced from its seed
+static RNG: Mutex<Option<StdRng>> = Mutex::new(None);
 
-#[allow(dead_code)]
-impl Timer {
-    pub fn new(label: &str) -> Self {
-        let mut x = Self {
-            times: vec![],
-        };
-        x.tick(label);
-        x
-    }
-    pub fn clear(&mut self) {
-        self.times.clear();
-    }
-    pub fn tick(&mut self, label: &str) -> (String, f64) {
-        self.times.push((label.to_string(), now()));
-        self.times.get(self.times.len().max(2)-2).unwrap_or(&("start".to_string(), 0.0)).clone()
-    }
-    pub fn diff_or_0(&self, a: usize, b: usize) -> f64 {
-        let a = self.times.get(a).unwrap_or(&("start".to_string(), 0.0)).1;
-        let b = self.times.get(b).unwrap_or(&("start".to_string(), a)).1;
-        a-b
-    }
-    pub fn show(&self) {
-        for i in 0..self.times.len() {
-            println!("{:>30}: {:.9}", self.times[i].0, self.diff_or_0(i, i.max(1)-1));
-        }
-        println!("{:>30}: {:.9}", "total", self.diff_or_0(self.times.len()-1, 0));
-    }
+pub fn seed(seed: u64) {
+    *RNG.lock().unwrap() = Some(StdRng::seed_from_u64(seed));
 }
 
+fn rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    let mut rng = RNG.lock().unwrap();
+    f(rng.get_or_insert_with(StdRng::from_entropy))
+}
 
 #[allow(dead_code)]
 pub fn rand_int(max: u32) -> u32 {
-    use rand::Rng;
-    rand::thread_rng().gen_range(0, max)
+    rng(|r| r.gen_range(0, max))
 }
 #[allow(dead_code)]
 pub fn rand_usize(max: usize) -> usize {
-    use rand::Rng;
-    rand::thread_rng().gen_range(0, max)
+    rng(|r| r.gen_range(0, max))
 }
 #[allow(dead_code)]
 pub fn rand_intr(min: i32, max: i32) -> i32 {
-    use rand::Rng;
-    rand::thread_rng().gen_range(min, max)
+    rng(|r| r.gen_range(min, max))
 }
 #[allow(dead_code)]
 pub fn rand_float(min: f32, max: f32) -> f32 {
-    use rand::Rng;
-    rand::thread_rng().gen_range(min, max)
+    rng(|r| r.gen_range(min, max))
 }
 #[allow(dead_code)]
 pub fn maybe(pty: f32) -> bool{
@@ -66,6 +43,27 @@ pub fn now() -> f64 {
     ste.as_secs() as f64 + ste.subsec_micros() as f64 / 1_000_000.0
 }
 
+static LOG_STDERR: AtomicBool = AtomicBool::new(false);
+
+// For when stdout carries something else, like a video stream
+pub fn log_to_stderr() {
+    LOG_STDERR.store(true, Ordering::Relaxed);
+}
+
+// Structured logging, stdout only gets one JSON object per line
+#[allow(dead_code)]
+pub fn log(event: &str, fields: serde_json::Value) {
+    let mut line = serde_json::json!({ "t": now(), "event": event });
+    if let (Some(l), serde_json::Value::Object(f)) = (line.as_object_mut(), fields) {
+        l.extend(f);
+    }
+    if LOG_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
 
 pub fn avg(numbers: &[f32]) -> f32 {
     numbers.iter().sum::<f32>() / numbers.len() as f32